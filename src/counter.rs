@@ -0,0 +1,165 @@
+//! Usage counters of the entries of a set.
+
+use std::cell::Cell;
+use std::fmt::Debug;
+use std::rc::{self, Rc};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{self, Arc};
+
+/// Message of decrementing a count of zero.
+const UNDERFLOW: &str = "usage count decremented below zero";
+
+/// The kind of usage counter of the entries of a set.
+///
+/// Each entry owns a [`Handle`](#associatedtype.Handle) that is shared with
+/// all `RcIndex`es of the entry. The counter types themselves are only used
+/// as type parameters.
+pub trait Counter {
+    /// Shared handle to the usage count of one entry.
+    ///
+    /// Cloning the handle must share the count, not copy it.
+    type Handle: Clone + Debug;
+    /// Non-owning handle to the usage count of one entry.
+    ///
+    /// It must not keep the count alive, i.e. after the entry and all of its
+    /// `Handle`s are dropped upgrading fails.
+    type Weak: Clone + Debug;
+    /// Shared flag of a set that is raised whenever a count drops to zero.
+    type Flag: Clone + Debug;
+
+    /// A new count of zero.
+    fn new_handle() -> Self::Handle;
+    /// Increment the count.
+    fn inc(handle: &Self::Handle);
+    /// Decrement the count. Returns `true` if the count reached zero.
+    ///
+    /// A count of zero must not be decremented. This is a bug that panics in
+    /// debug builds. In release builds the count stays zero.
+    fn dec(handle: &Self::Handle) -> bool;
+    /// Get the current count.
+    fn get(handle: &Self::Handle) -> usize;
+    /// Returns `true` if both handles share the same count.
+    fn ptr_eq(a: &Self::Handle, b: &Self::Handle) -> bool;
+    /// Get a non-owning handle to the count.
+    fn downgrade(handle: &Self::Handle) -> Self::Weak;
+    /// Get back a handle if the count is still alive.
+    fn upgrade(weak: &Self::Weak) -> Option<Self::Handle>;
+    /// Number of non-owning handles to the count.
+    fn weak_count(handle: &Self::Handle) -> usize;
+    /// A new, lowered flag.
+    fn new_flag() -> Self::Flag;
+    /// Raise the flag.
+    fn raise(flag: &Self::Flag);
+    /// Lower the flag. Returns `true` if it was raised.
+    fn take(flag: &Self::Flag) -> bool;
+}
+
+/// Single-threaded counter based on `Rc<Cell<usize>>`.
+///
+/// This is the default counter of the sets.
+#[derive(Debug)]
+pub enum RcCounter {}
+
+impl Counter for RcCounter {
+    type Handle = Rc<Cell<usize>>;
+    type Weak = rc::Weak<Cell<usize>>;
+    type Flag = Rc<Cell<bool>>;
+
+    fn new_handle() -> Self::Handle {
+        Default::default()
+    }
+    fn inc(handle: &Self::Handle) {
+        handle.set(handle.get() + 1);
+    }
+    fn dec(handle: &Self::Handle) -> bool {
+        let cnt = handle.get();
+        debug_assert!(cnt > 0, "{}", UNDERFLOW);
+        // a wrapped count would keep the entry forever
+        match cnt.checked_sub(1) {
+            Some(cnt) => {
+                handle.set(cnt);
+                cnt == 0
+            }
+            None => false,
+        }
+    }
+    fn get(handle: &Self::Handle) -> usize {
+        handle.get()
+    }
+    fn ptr_eq(a: &Self::Handle, b: &Self::Handle) -> bool {
+        Rc::ptr_eq(a, b)
+    }
+    fn downgrade(handle: &Self::Handle) -> Self::Weak {
+        Rc::downgrade(handle)
+    }
+    fn upgrade(weak: &Self::Weak) -> Option<Self::Handle> {
+        weak.upgrade()
+    }
+    fn weak_count(handle: &Self::Handle) -> usize {
+        Rc::weak_count(handle)
+    }
+    fn new_flag() -> Self::Flag {
+        Default::default()
+    }
+    fn raise(flag: &Self::Flag) {
+        flag.set(true);
+    }
+    fn take(flag: &Self::Flag) -> bool {
+        flag.replace(false)
+    }
+}
+
+/// Thread-safe counter based on `Arc<AtomicUsize>`.
+///
+/// With this counter the `RcIndex`es are `Send` and `Sync` and can be
+/// cloned and dropped on other threads than the one of the set.
+#[derive(Debug)]
+pub enum ArcCounter {}
+
+impl Counter for ArcCounter {
+    type Handle = Arc<AtomicUsize>;
+    type Weak = sync::Weak<AtomicUsize>;
+    type Flag = Arc<AtomicBool>;
+
+    fn new_handle() -> Self::Handle {
+        Default::default()
+    }
+    fn inc(handle: &Self::Handle) {
+        handle.fetch_add(1, Ordering::Relaxed);
+    }
+    fn dec(handle: &Self::Handle) -> bool {
+        match handle.fetch_update(Ordering::Release, Ordering::Relaxed, |cnt| {
+            cnt.checked_sub(1)
+        }) {
+            Ok(prev) => prev == 1,
+            Err(_) => {
+                debug_assert!(false, "{}", UNDERFLOW);
+                false
+            }
+        }
+    }
+    fn get(handle: &Self::Handle) -> usize {
+        handle.load(Ordering::Acquire)
+    }
+    fn ptr_eq(a: &Self::Handle, b: &Self::Handle) -> bool {
+        Arc::ptr_eq(a, b)
+    }
+    fn downgrade(handle: &Self::Handle) -> Self::Weak {
+        Arc::downgrade(handle)
+    }
+    fn upgrade(weak: &Self::Weak) -> Option<Self::Handle> {
+        weak.upgrade()
+    }
+    fn weak_count(handle: &Self::Handle) -> usize {
+        Arc::weak_count(handle)
+    }
+    fn new_flag() -> Self::Flag {
+        Default::default()
+    }
+    fn raise(flag: &Self::Flag) {
+        flag.store(true, Ordering::Release);
+    }
+    fn take(flag: &Self::Flag) -> bool {
+        flag.swap(false, Ordering::Acquire)
+    }
+}
@@ -0,0 +1,115 @@
+//! The entry API of the set, see
+//! [`IndexedHashSet::entry()`](../struct.IndexedHashSet.html#method.entry).
+
+use crate::{Counter, IndexedHashSet, RcCounter, RcIndex};
+use generational_arena::Index as AIndex;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+
+/// A view into a single element of a set which is either present or not.
+///
+/// Created by [`IndexedHashSet::entry()`](../struct.IndexedHashSet.html#method.entry).
+#[derive(Debug)]
+pub enum Entry<'a, T, C: Counter = RcCounter, S = RandomState> {
+    /// An equal element is in the set.
+    Occupied(OccupiedEntry<'a, T, C, S>),
+    /// No equal element is in the set.
+    Vacant(VacantEntry<'a, T, C, S>),
+}
+
+impl<'a, T, C, S> Entry<'a, T, C, S>
+where
+    T: Eq + Hash,
+    C: Counter,
+    S: BuildHasher,
+{
+    /// Inserts the element if vacant and returns an index to the entry.
+    ///
+    /// # Panics
+    ///
+    /// Just as [`insert()`](../struct.IndexedHashSet.html#method.insert) this
+    /// panics if a set with a maximum number of entries is full.
+    pub fn or_insert(self) -> RcIndex<C> {
+        match self {
+            Entry::Occupied(occupied) => occupied.index(),
+            Entry::Vacant(vacant) => vacant.insert(),
+        }
+    }
+    /// Inserts the element produced by `f` if vacant and returns an index to
+    /// the entry.
+    ///
+    /// `f` is only called if the entry is vacant. The carried element is
+    /// dropped in that case.
+    ///
+    /// # Panics
+    ///
+    /// The produced element must be equal to the carried one, which is
+    /// checked in debug builds. Besides, just as
+    /// [`insert()`](../struct.IndexedHashSet.html#method.insert) this panics
+    /// if a set with a maximum number of entries is full.
+    pub fn or_insert_with<F: FnOnce() -> T>(self, f: F) -> RcIndex<C> {
+        match self {
+            Entry::Occupied(occupied) => occupied.index(),
+            Entry::Vacant(vacant) => vacant.insert_with(f),
+        }
+    }
+}
+
+/// A view into an element that is present in the set.
+#[derive(Debug)]
+pub struct OccupiedEntry<'a, T, C: Counter = RcCounter, S = RandomState> {
+    pub(crate) set: &'a mut IndexedHashSet<T, C, S>,
+    pub(crate) a_idx: AIndex,
+}
+
+impl<'a, T, C, S> OccupiedEntry<'a, T, C, S>
+where
+    T: Eq + Hash,
+    C: Counter,
+    S: BuildHasher,
+{
+    /// The element in the set.
+    pub fn get(&self) -> &T {
+        self.set.arena[self.a_idx].elem()
+    }
+    /// Get an index to the entry.
+    pub fn index(&self) -> RcIndex<C> {
+        self.set.aidx_to_rcidx(self.a_idx)
+    }
+}
+
+/// A view into an element that is absent from the set.
+#[derive(Debug)]
+pub struct VacantEntry<'a, T, C: Counter = RcCounter, S = RandomState> {
+    pub(crate) set: &'a mut IndexedHashSet<T, C, S>,
+    pub(crate) hash: u64,
+    pub(crate) elem: T,
+}
+
+impl<'a, T, C, S> VacantEntry<'a, T, C, S>
+where
+    T: Eq + Hash,
+    C: Counter,
+    S: BuildHasher,
+{
+    /// The element that would be inserted.
+    pub fn get(&self) -> &T {
+        &self.elem
+    }
+    /// Inserts the element and returns an index to the new entry.
+    ///
+    /// # Panics
+    ///
+    /// Just as [`insert()`](../struct.IndexedHashSet.html#method.insert) this
+    /// panics if a set with a maximum number of entries is full.
+    pub fn insert(self) -> RcIndex<C> {
+        self.set.insert_unchecked(self.hash, self.elem)
+    }
+    /// Inserts the element produced by `f` instead of the carried one.
+    fn insert_with<F: FnOnce() -> T>(self, f: F) -> RcIndex<C> {
+        let elem = f();
+        debug_assert!(elem == self.elem, "produced element differs from entry");
+        // equal to the carried element, so the hash is the same
+        self.set.insert_unchecked(self.hash, elem)
+    }
+}
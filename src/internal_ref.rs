@@ -10,20 +10,6 @@ use std::ptr::NonNull;
 #[derive(Copy, Clone, Eq, PartialEq)]
 pub struct InternalRef<T: ?Sized>(NonNull<T>);
 
-impl<T> InternalRef<T> {
-    /// Build an `InternalRef` from a reference.
-    ///
-    /// # Warning
-    ///
-    /// An `InternalRef` removes the reference's lifetime. This is necessary
-    /// to build the self-referential hash-to-index-map of the `IndexedHashSet`.
-    /// Therefore, it is important to check the usage of `InternalRef`
-    /// carefully to avoid use-after-free bugs!
-    pub fn from_ref(t: &T) -> Self {
-        InternalRef(t.into())
-    }
-}
-
 impl<T: fmt::Debug> fmt::Debug for InternalRef<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "InternalRef to {:?}", self.as_ref())
@@ -53,6 +39,67 @@ where
     }
 }
 
+/// Owned, heap allocated element of the set. Only used internal.
+///
+/// This is used instead of a `Box<T>` as the `InternalRef`s in the map of the
+/// set point into it. Moving or mutably borrowing a `Box` asserts unique
+/// access to its content, which would invalidate these references, e.g. when
+/// the arena grows or an element is replaced. All accesses to the element are
+/// derived from the same raw pointer as the `InternalRef`s instead.
+pub struct ElemBox<T>(NonNull<T>);
+
+impl<T> ElemBox<T> {
+    /// Move `t` to the heap.
+    pub fn new(t: T) -> Self {
+        ElemBox(unsafe { NonNull::new_unchecked(Box::into_raw(Box::new(t))) })
+    }
+    /// Build an `InternalRef` to the element.
+    ///
+    /// # Warning
+    ///
+    /// An `InternalRef` removes the lifetime of the element. This is necessary
+    /// to build the self-referential hash-to-index-map of the `IndexedHashSet`.
+    /// Therefore, it is important to check the usage of `InternalRef`
+    /// carefully to avoid use-after-free bugs!
+    pub fn internal_ref(&self) -> InternalRef<T> {
+        InternalRef(self.0)
+    }
+    /// Move the element back from the heap.
+    pub fn into_inner(self) -> T {
+        let ptr = self.0;
+        std::mem::forget(self);
+        *unsafe { Box::from_raw(ptr.as_ptr()) }
+    }
+}
+
+impl<T> AsRef<T> for ElemBox<T> {
+    fn as_ref(&self) -> &T {
+        unsafe { self.0.as_ref() }
+    }
+}
+
+impl<T> AsMut<T> for ElemBox<T> {
+    fn as_mut(&mut self) -> &mut T {
+        unsafe { self.0.as_mut() }
+    }
+}
+
+impl<T> Drop for ElemBox<T> {
+    fn drop(&mut self) {
+        drop(unsafe { Box::from_raw(self.0.as_ptr()) });
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for ElemBox<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.as_ref().fmt(f)
+    }
+}
+
+/// The element is owned just like by a `Box`.
+unsafe impl<T: Send> Send for ElemBox<T> {}
+unsafe impl<T: Sync> Sync for ElemBox<T> {}
+
 /// A helper struct to allow using `InternalRef` as key in a `HashSet`
 ///
 /// # Details
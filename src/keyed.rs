@@ -0,0 +1,329 @@
+//! A variant of the set whose entries are distinct by a key extracted from the
+//! stored values.
+
+use crate::{Counter, Entry, IndexError, RcCounter, RcIndex};
+use generational_arena::{Arena, Index as AIndex};
+use hashbrown::HashMap;
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::hash::Hash;
+
+/// An indexed hash set whose entries are distinct by a key of the values.
+///
+/// In contrast to [`IndexedHashSet`] the values themselves need not implement
+/// `Hash` and `Eq`. Instead, a key is extracted from each value by the
+/// function given on construction. Two values with equal keys are considered
+/// to be the same entry. The set stores a clone of each key.
+///
+/// As only the key is relevant for hashing, the remaining parts of a value
+/// can be mutated via [`get_mut_by_index()`](#method.get_mut_by_index).
+///
+/// The usage counts are shared with `!Send` `RcIndex`es. Hence, the set is
+/// not `Send`:
+///
+/// ```compile_fail
+/// # use indexed_hash_set::KeyedIndexedHashSet;
+/// fn assert_send<S: Send>(_: S) {}
+///
+/// assert_send(KeyedIndexedHashSet::<u32, (u32, u32)>::new(|v| &v.0));
+/// ```
+///
+/// [`IndexedHashSet`]: ./struct.IndexedHashSet.html
+#[derive(Debug)]
+pub struct KeyedIndexedHashSet<K, V> {
+    /// The actual store of entries.
+    arena: Arena<Entry<V>>,
+    /// Map from keys to indices that ultimately retrieve the entries.
+    ///
+    /// The keys are clones of the keys of the values. In contrast to the
+    /// self-references of the `IndexedHashSet` they stay valid even if a
+    /// value is mutated, e.g. a boxed key is replaced.
+    map: HashMap<K, AIndex, RandomState>,
+    /// Extracts the key from a value.
+    key: fn(&V) -> &K,
+    /// Raised by the indices of the set when a usage count drops to zero.
+    dirty: <RcCounter as Counter>::Flag,
+}
+
+impl<K, V> KeyedIndexedHashSet<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    /// A new, empty set using `key` to extract the key of a value.
+    ///
+    /// The key must be part of the value and must be the same for each call
+    /// with the same value.
+    pub fn new(key: fn(&V) -> &K) -> Self {
+        Self {
+            arena: Default::default(),
+            map: Default::default(),
+            key,
+            dirty: RcCounter::new_flag(),
+        }
+    }
+    /// Number of elements in the set, including the unused ones.
+    pub fn len(&self) -> usize {
+        self.arena.len()
+    }
+    /// Returns `true` if the set contains no elements, including unused ones.
+    pub fn is_empty(&self) -> bool {
+        self.arena.is_empty()
+    }
+    /// Get the usage count of an element by its key.
+    pub fn get_cnt<Q>(&self, key: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let idx = self.map.get(key)?;
+        Some(self.arena[*idx].cnt())
+    }
+    /// Get a reference to the stored element by its key.
+    pub fn get_ref_by_hash<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let idx = self.map.get(key)?;
+        Some(self.arena[*idx].elem())
+    }
+    /// Get a mutable reference to the stored element by its key.
+    ///
+    /// _Note:_ The key of the element must not be changed, see
+    /// [`get_mut_by_index()`](#method.get_mut_by_index).
+    pub fn get_mut_by_hash<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let idx = *self.map.get(key)?;
+        Some(self.arena[idx].elem.as_mut())
+    }
+    /// Get the index of the stored element by its key.
+    pub fn get_index_by_hash<Q>(&self, key: &Q) -> Option<RcIndex>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let a_idx = self.map.get(key)?;
+        Some(self.aidx_to_rcidx(*a_idx))
+    }
+    /// Get a reference to the stored element by index.
+    ///
+    /// As the index can be from another set this operation is fallible.
+    pub fn get_ref_by_index(&self, idx: &RcIndex) -> Option<&V> {
+        let entry = self.arena.get(idx.inner)?;
+        Some(entry.elem())
+    }
+    /// Get a reference to the stored element by index or the reason why
+    /// this is not possible.
+    ///
+    /// See [`IndexedHashSet::get_ref_by_index_result()`](./struct.IndexedHashSet.html#method.get_ref_by_index_result).
+    pub fn get_ref_by_index_result(&self, idx: &RcIndex) -> Result<&V, IndexError> {
+        let (slot, _) = idx.inner.into_raw_parts();
+        match self.arena.get_unknown_gen(slot) {
+            Some((entry, a_idx)) if a_idx == idx.inner => {
+                if RcCounter::ptr_eq(&entry.usage_cnt, &idx.cnt) {
+                    Ok(entry.elem())
+                } else {
+                    Err(IndexError::Foreign)
+                }
+            }
+            Some(_) => Err(IndexError::Stale),
+            None if slot < self.arena.capacity() => Err(IndexError::Collected),
+            None => Err(IndexError::Foreign),
+        }
+    }
+    /// Get a mutable reference to the stored element by index.
+    ///
+    /// As the index can be from another set this operation is fallible.
+    ///
+    /// _Note:_ The key of the element should not be changed. The element
+    /// stays in the set under its original key, i.e. it can no longer be found
+    /// by its new key.
+    pub fn get_mut_by_index(&mut self, idx: &RcIndex) -> Option<&mut V> {
+        let entry = self.arena.get_mut(idx.inner)?;
+        Some(entry.elem.as_mut())
+    }
+    /// Insert a new element into the set.
+    ///
+    /// If an element with an equal key is already in the set `None` is
+    /// returned else the index of the new entry is returned.
+    ///
+    /// _Note:_ The returned `RcIndex` is the initial usage of the entry. If it
+    /// is dropped without cloning the `usage_cnt` goes to zero and the new
+    /// element is dropped on the next [`drop_unused()`](#method.drop_unused)!
+    #[must_use = "If not stored usage count of the new element goes to zero."]
+    pub fn insert(&mut self, elem: V) -> Option<RcIndex> {
+        let key = (self.key)(&elem);
+        if self.map.contains_key(key) {
+            return None;
+        }
+        let key = key.clone();
+
+        let entry = Entry::new(elem);
+        let cnt_handle = entry.cnt_handle();
+
+        let a_idx = self.arena.insert(entry);
+        self.map.insert(key, a_idx);
+
+        Some(RcIndex::new(a_idx, cnt_handle, self.dirty.clone()))
+    }
+    /// Drop all entries whose `usage_cnt` is zero.
+    ///
+    /// The arena is only scanned if a usage count dropped to zero since the
+    /// last call.
+    pub fn drop_unused(&mut self) -> usize {
+        if !RcCounter::take(&self.dirty) {
+            return 0;
+        }
+
+        let arena = &mut self.arena;
+        let before = arena.len();
+
+        // the stored keys are used, so a changed key of a value leaves no
+        // dangling entry in the map
+        self.map.retain(|_, a_idx| arena[*a_idx].cnt() != 0);
+        arena.retain(|_, entry| entry.cnt() != 0);
+
+        before - arena.len()
+    }
+    /// Iterates over all elements in the set with `usage_cnt != 0`.
+    pub fn iter(&self) -> impl Iterator<Item = &V> {
+        self.arena
+            .iter()
+            .filter_map(|(_, e)| if e.cnt() != 0 { Some(e.elem()) } else { None })
+    }
+    /// Returns the respective `RcIndex` for an index of the arena.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the arena index is not present.
+    fn aidx_to_rcidx(&self, a_idx: AIndex) -> RcIndex {
+        let entry = &self.arena[a_idx];
+        RcIndex::new(a_idx, entry.cnt_handle(), self.dirty.clone())
+    }
+}
+
+/// Allows to access the set like `set[&rc_idx]`.
+///
+/// This panics if the `RcIndex` used is not from this `KeyedIndexedHashSet`.
+impl<'a, K, V> std::ops::Index<&'a RcIndex> for KeyedIndexedHashSet<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    type Output = V;
+
+    fn index(&self, index: &'a RcIndex) -> &Self::Output {
+        match self.get_ref_by_index_result(index) {
+            Ok(elem) => elem,
+            Err(err) => panic!("{}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Record {
+        id: u32,
+        name: String,
+        visits: usize,
+    }
+
+    impl Record {
+        fn new(id: u32, name: &str) -> Self {
+            Self {
+                id,
+                name: name.to_owned(),
+                visits: 0,
+            }
+        }
+        fn id(&self) -> &u32 {
+            &self.id
+        }
+    }
+
+    #[test]
+    fn dedup_by_key() {
+        let mut set = KeyedIndexedHashSet::new(Record::id);
+        let _olaf = set.insert(Record::new(1, "Olaf")).unwrap();
+        assert!(set.insert(Record::new(1, "Eijnar")).is_none());
+        assert_eq!(set.len(), 1);
+        assert_eq!(set.get_ref_by_hash(&1).unwrap().name, "Olaf");
+    }
+
+    #[test]
+    fn mutate_non_key_fields() {
+        let mut set = KeyedIndexedHashSet::new(Record::id);
+        let olaf = set.insert(Record::new(1, "Olaf")).unwrap();
+        let _harald = set.insert(Record::new(2, "Harald")).unwrap();
+
+        for _ in 0..3 {
+            set.get_mut_by_index(&olaf).unwrap().visits += 1;
+        }
+        set.get_mut_by_index(&olaf)
+            .unwrap()
+            .name
+            .push_str(" the Stout");
+
+        let olaf_ref = set.get_ref_by_hash(&1).unwrap();
+        assert_eq!(olaf_ref.visits, 3);
+        assert_eq!(olaf_ref.name, "Olaf the Stout");
+        assert_eq!(set.get_ref_by_hash(&2).unwrap().name, "Harald");
+        assert_eq!(set[&olaf].visits, 3);
+    }
+
+    #[test]
+    fn mutate_by_key() {
+        let mut set = KeyedIndexedHashSet::new(Record::id);
+        let olaf = set.insert(Record::new(1, "Olaf")).unwrap();
+        set.get_mut_by_hash(&1).unwrap().visits += 1;
+        assert!(set.get_mut_by_hash(&2).is_none());
+        assert_eq!(set[&olaf].visits, 1);
+    }
+
+    #[test]
+    fn replace_boxed_key() {
+        let mut set = KeyedIndexedHashSet::new(|v: &(Box<u64>, u32)| &*v.0);
+        let first = set.insert((Box::new(1), 10)).unwrap();
+        let _second = set.insert((Box::new(2), 20)).unwrap();
+
+        let value = set.get_mut_by_index(&first).unwrap();
+        let old = std::mem::replace(&mut value.0, Box::new(3));
+        // frees the memory of the original key
+        drop(old);
+        assert_eq!(set.get_ref_by_hash(&1).unwrap().1, 10);
+        assert!(set.get_ref_by_hash(&3).is_none());
+        assert_eq!(set.get_ref_by_hash(&2).unwrap().1, 20);
+
+        drop(first);
+        assert_eq!(set.drop_unused(), 1);
+        assert!(set.get_ref_by_hash(&1).is_none());
+        assert_eq!(set.len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "the index is from another set")]
+    fn index_foreign_panics() {
+        let mut set = KeyedIndexedHashSet::new(Record::id);
+        let _olaf = set.insert(Record::new(1, "Olaf")).unwrap();
+        let mut other = KeyedIndexedHashSet::new(Record::id);
+        let harald = other.insert(Record::new(2, "Harald")).unwrap();
+        let _ = &set[&harald];
+    }
+
+    #[test]
+    fn unused_entries() {
+        let mut set = KeyedIndexedHashSet::new(Record::id);
+        let olaf = set.insert(Record::new(1, "Olaf")).unwrap();
+        set.insert(Record::new(2, "Harald")).unwrap();
+        assert_eq!(set.get_cnt(&1), Some(1));
+        assert_eq!(set.drop_unused(), 1);
+        assert!(set.get_ref_by_hash(&2).is_none());
+        assert_eq!(set.get_index_by_hash(&1).unwrap().cnt(), 2);
+        assert_eq!(olaf.cnt(), 1);
+    }
+}
@@ -7,107 +7,735 @@
 //! - Internal a [generational arena] is used to allow for effective mutation
 //!   of the set.
 //!
+//! If the elements should be distinct by only a part of them, e.g. an ID, use
+//! a [`KeyedIndexedHashSet`] instead.
+//!
+//! # Custom equality
+//!
+//! The elements are distinct by their `Hash` and `Eq` implementations. For
+//! another notion of equality wrap the elements in a type implementing it.
+//! For example, strings can be interned case-insensitively while the casing
+//! of the first insertion is preserved:
+//!
+//! ```
+//! # use indexed_hash_set::IndexedHashSet;
+//! use std::hash::{Hash, Hasher};
+//!
+//! #[derive(Debug)]
+//! struct CaseFold(String);
+//!
+//! impl PartialEq for CaseFold {
+//!     fn eq(&self, other: &Self) -> bool {
+//!         self.0.eq_ignore_ascii_case(&other.0)
+//!     }
+//! }
+//!
+//! impl Eq for CaseFold {}
+//!
+//! impl Hash for CaseFold {
+//!     fn hash<H: Hasher>(&self, state: &mut H) {
+//!         for b in self.0.bytes() {
+//!             state.write_u8(b.to_ascii_lowercase());
+//!         }
+//!     }
+//! }
+//!
+//! let mut set = IndexedHashSet::new();
+//! let (foo, _) = set.intern(CaseFold("Foo".to_owned()));
+//! let (again, stored) = set.intern(CaseFold("foo".to_owned()));
+//! assert_eq!(stored.0, "Foo");
+//! assert_eq!(foo, again);
+//! assert_eq!(set.len(), 1);
+//! assert!(set.contains(&CaseFold("FOO".to_owned())));
+//! ```
+//!
+//! # Features
+//!
+//! - `rayon`: Parallel collection of unused entries of sets with the
+//!   `ArcCounter`.
+//! - `serde`: (De)serialization of sets as sequences of their elements.
+//! - `tracing`: Events on insertions, collections of unused entries and
+//!   creation and drop of indices via [`tracing`].
+//!
 //! [`drop_unused()`]: ./struct.IndexedHashSet.html#method.drop_unused
 //! [generational arena]: https://docs.rs/generational-arena/latest/
+//! [`KeyedIndexedHashSet`]: ./struct.KeyedIndexedHashSet.html
+//! [`tracing`]: https://docs.rs/tracing/latest/
 
 #![deny(missing_docs)]
 
 use generational_arena::{Arena, Index as AIndex};
+use hashbrown::hash_map::{HashMap, RawEntryMut};
 use std::borrow::Borrow;
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::hash::Hash;
-use std::rc::Rc;
+use std::cmp::Ordering;
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeMap;
+use std::hash::{BuildHasher, Hash};
+use std::sync::atomic::{self, AtomicU64};
+
+/// Emits a `tracing` event if the `tracing` feature is enabled.
+///
+/// Without the feature the arguments are not even evaluated.
+macro_rules! trace_event {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::event!($($arg)*);
+    };
+}
+
+mod counter;
+pub use self::counter::{ArcCounter, Counter, RcCounter};
+
+pub use hashbrown::TryReserveError;
+
+/// Raw index into the arena of a set, see
+/// [`RcIndex::into_raw()`](./struct.RcIndex.html#method.into_raw).
+pub use generational_arena::Index as ArenaIndex;
+
+pub mod entry;
 
 mod internal_ref;
-use self::internal_ref::{InternalRef, Wrap as _};
+use self::internal_ref::{ElemBox, InternalRef, Wrap as _};
+
+mod keyed;
+pub use self::keyed::KeyedIndexedHashSet;
+
+#[cfg(feature = "rayon")]
+mod rayon_impls;
+
+#[cfg(feature = "serde")]
+mod serde_impls;
 
 /// An entry in the set.
 #[derive(Debug)]
-struct Entry<T> {
+struct Entry<T, C: Counter = RcCounter> {
     /// Elements are boxed to allow correct self-references in the
     /// element-to-index-map. Otherwise a re-allocation of the arena due to
-    /// growth could invalidate the supporting map.
-    elem: Box<T>,
+    /// growth could invalidate the supporting map. An `ElemBox` instead of a
+    /// `Box` also keeps the self-references valid if the element is mutated.
+    elem: ElemBox<T>,
     /// Count of existing indices referencing this entry. If this is zero the
     /// entry can be dropped.
-    usage_cnt: Rc<RefCell<usize>>,
+    usage_cnt: C::Handle,
+    /// Epoch of the set when the entry was last touched.
+    ///
+    /// It is atomic to allow reading entries from several threads, see
+    /// `par_drop_unused()`. A relaxed ordering suffices as the stamp carries
+    /// no other data.
+    touched: AtomicU64,
+    /// Position of the entry in the sequence of insertions into the set.
+    ///
+    /// It is assigned once on insertion and never changed afterwards, so
+    /// removing other entries keeps the order of the survivors.
+    seq: u64,
+    /// Pinned entries are kept even if their `usage_cnt` is zero.
+    pinned: bool,
 }
 
-impl<T> Entry<T> {
+impl<T, C: Counter> Entry<T, C> {
     /// A new entry with a `usage_cnt` of zero.
     fn new(elem: T) -> Self {
         Entry {
-            elem: Box::new(elem),
-            usage_cnt: Default::default(),
+            elem: ElemBox::new(elem),
+            usage_cnt: C::new_handle(),
+            touched: AtomicU64::new(0),
+            seq: 0,
+            pinned: false,
         }
     }
-    fn cnt_handle(&self) -> Rc<RefCell<usize>> {
+    fn cnt_handle(&self) -> C::Handle {
         self.usage_cnt.clone()
     }
     fn cnt(&self) -> usize {
-        *(*self.usage_cnt).borrow()
+        C::get(&self.usage_cnt)
     }
     fn elem(&self) -> &T {
         self.elem.as_ref()
     }
+    /// An entry is used if it is pinned or referenced by an index.
+    ///
+    /// The count lives in an allocation shared with the indices, so this reads
+    /// that allocation. A liveness flag cached in the entry can not replace
+    /// it as the indices decrement the count without access to the entry.
+    fn is_used(&self) -> bool {
+        self.pinned || self.cnt() != 0
+    }
+}
+
+/// Removes an entry from the arena unless it is forgotten.
+///
+/// This rolls back an insertion into the arena if the following insertion
+/// into the map panics.
+struct RemoveOnUnwind<'a, T, C: Counter> {
+    arena: &'a mut Arena<Entry<T, C>>,
+    a_idx: AIndex,
+}
+
+impl<T, C: Counter> Drop for RemoveOnUnwind<'_, T, C> {
+    fn drop(&mut self) {
+        self.arena.remove(self.a_idx);
+    }
 }
 
 /// An indexed hash set. Can be accessed either by index of hashing.
+///
+/// The kind of usage counters of the entries can be chosen by `C`, see
+/// [`Counter`](./trait.Counter.html). By default the single-threaded
+/// [`RcCounter`](./enum.RcCounter.html) is used.
+///
+/// The elements are hashed by the `BuildHasher` `S`, see
+/// [`with_hasher()`](#method.with_hasher). By default std's `RandomState` is
+/// used.
+///
+/// The elements need not be `'static`. The internal self-references only
+/// point to elements owned by the set and never outlive it. In turn, the set
+/// can not outlive data borrowed by its elements.
 #[derive(Debug)]
-pub struct IndexedHashSet<T>
+pub struct IndexedHashSet<T, C = RcCounter, S = RandomState>
 where
-    T: 'static,
+    C: Counter,
 {
     /// The actual store of entries.
-    arena: Arena<Entry<T>>,
+    arena: Arena<Entry<T, C>>,
     /// Map from elements to indices that ultimately retrieve the entries.
     ///
-    /// The keys are fake `'static`. Actually they **self-reference** the
-    /// entries in the arena.
-    map: HashMap<InternalRef<T>, AIndex>,
+    /// The keys are references without a lifetime. Actually they
+    /// **self-reference** the entries in the arena.
+    map: HashMap<InternalRef<T>, AIndex, S>,
+    /// Arena slot where the next [`drop_unused_bounded()`] continues.
+    ///
+    /// [`drop_unused_bounded()`]: #method.drop_unused_bounded
+    gc_cursor: usize,
+    /// Maximum number of entries, if configured.
+    max_entries: Option<usize>,
+    /// Current epoch with which touched entries are stamped.
+    epoch: u64,
+    /// Sequence number of the next inserted entry.
+    next_seq: u64,
+    /// Raised by the indices of the set when a usage count drops to zero.
+    ///
+    /// If it is lowered there are no unused entries to drop.
+    dirty: C::Flag,
+    /// Statistics over the lifetime of the set.
+    metrics: GcMetrics,
 }
 
 impl<T> IndexedHashSet<T>
 where
-    T: 'static + Eq + Hash,
+    T: Eq + Hash,
 {
     /// A new, empty set.
+    ///
+    /// Sets with other counters can be created via `Default`, e.g.
+    /// `IndexedHashSet::<T, ArcCounter>::default()`.
     pub fn new() -> Self {
         Default::default()
     }
+    /// A new, empty set that holds at most `max` entries, including the
+    /// unused ones.
+    ///
+    /// When the set is full an insertion first drops the unused entries via
+    /// [`drop_unused()`](#method.drop_unused) to make room. Only if the set is
+    /// still full afterwards the insertion fails, see
+    /// [`try_insert()`](#method.try_insert).
+    pub fn with_max_entries(max: usize) -> Self {
+        Self::with_max_entries_and_counter(max)
+    }
+    /// A new, empty set that can hold at least `capacity` elements without
+    /// reallocating.
+    ///
+    /// All slots of the arena are allocated up front, i.e. neither the arena
+    /// nor the map grows during the first `capacity` insertions.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut set = Self::new();
+        set.reserve(capacity);
+        set
+    }
+}
+
+impl<T, C, S> IndexedHashSet<T, C, S>
+where
+    T: Eq + Hash,
+    C: Counter,
+    S: BuildHasher,
+{
+    /// A new, empty set with the counter `C` that holds at most `max` entries.
+    ///
+    /// See [`with_max_entries()`](#method.with_max_entries).
+    pub fn with_max_entries_and_counter(max: usize) -> Self
+    where
+        S: Default,
+    {
+        Self {
+            max_entries: Some(max),
+            ..Default::default()
+        }
+    }
+    /// A new, empty set that uses `hasher` to hash the elements.
+    ///
+    /// In contrast to [`new()`](#method.new) the hasher need not implement
+    /// `Default`, e.g. a hasher with a random seed.
+    pub fn with_hasher(hasher: S) -> Self {
+        Self {
+            arena: Default::default(),
+            map: HashMap::with_hasher(hasher),
+            gc_cursor: 0,
+            max_entries: None,
+            epoch: 0,
+            next_seq: 0,
+            dirty: C::new_flag(),
+            metrics: GcMetrics::default(),
+        }
+    }
+    /// A new, empty set that uses `hasher` to hash the elements and can hold
+    /// at least `capacity` elements without reallocating.
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
+        let mut set = Self::with_hasher(hasher);
+        set.reserve(capacity);
+        set
+    }
+    /// The hasher used by the set.
+    ///
+    /// This is the instance that hashes the elements, e.g. for
+    /// [`hash_of()`](#method.hash_of).
+    pub fn hasher(&self) -> &S {
+        self.map.hasher()
+    }
     /// Number of elements in the set, including the unused ones.
     pub fn len(&self) -> usize {
         self.arena.len()
     }
+    /// Number of used elements in the set, i.e. the number of elements
+    /// yielded by [`iter()`](#method.iter).
+    ///
+    /// In contrast to [`len()`](#method.len) all entries are scanned.
+    pub fn used_len(&self) -> usize {
+        self.arena.iter().filter(|(_, e)| e.is_used()).count()
+    }
+    /// Total number of outstanding indices to the entries of the set.
+    ///
+    /// In contrast to [`used_len()`](#method.used_len) this counts the
+    /// indices, not the entries. A total that never drops hints at leaked
+    /// indices. All entries are scanned.
+    pub fn total_references(&self) -> usize {
+        self.arena.iter().map(|(_, e)| e.cnt()).sum()
+    }
+    /// Get the usage count and the number of weak handles of an entry by
+    /// index.
+    ///
+    /// The first number is the same as for
+    /// [`get_cnt_by_index()`](#method.get_cnt_by_index). The second counts all
+    /// handles that do not keep the entry alive, i.e.
+    /// [`WeakIndex`](./struct.WeakIndex.html)es,
+    /// [`LazyIndex`](./struct.LazyIndex.html)es and
+    /// [`CountObserver`](./struct.CountObserver.html)s.
+    pub fn ref_counts_by_index(&self, idx: &RcIndex<C>) -> Option<(usize, usize)> {
+        let entry = self.arena.get(idx.inner)?;
+        Some((entry.cnt(), C::weak_count(&entry.usage_cnt)))
+    }
+    /// Checks the internal invariants of the set, e.g. in tests.
+    ///
+    /// This is a no-op in release builds.
+    ///
+    /// # Panics
+    ///
+    /// This panics with a description of the violated invariant, which is
+    /// always a bug of this crate.
+    pub fn assert_consistent(&self) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+        assert_eq!(
+            self.map.len(),
+            self.arena.len(),
+            "map and arena have a different number of entries"
+        );
+        for (key, a_idx) in &self.map {
+            let entry = self
+                .arena
+                .get(*a_idx)
+                .expect("map points to an empty slot of the arena");
+            assert!(
+                std::ptr::eq(key.as_ref(), entry.elem()),
+                "map key does not reference the element of its entry"
+            );
+        }
+        assert_eq!(
+            self.iter().count(),
+            self.used_len(),
+            "iter() does not yield all used elements"
+        );
+    }
+    /// Number of elements the set can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.arena.capacity().min(self.map.capacity())
+    }
+    /// Reserves capacity for at least `additional` more elements.
+    ///
+    /// As the elements are boxed, a reallocation never invalidates them.
+    /// Nevertheless, reserving up front avoids repeated growth when many
+    /// elements are inserted.
+    pub fn reserve(&mut self, additional: usize) {
+        let required = self.arena.len().saturating_add(additional);
+        let arena_capacity = self.arena.capacity();
+        if required > arena_capacity {
+            self.arena.reserve(required - arena_capacity);
+        }
+        self.map.reserve(additional);
+    }
+    /// Rebuilds the map of the set without changing its capacity.
+    ///
+    /// Removing many elements leaves tombstones in the map which lengthen
+    /// lookups. Rebuilding removes them. The elements are not moved, i.e.
+    /// indices and element addresses stay valid.
+    pub fn rehash(&mut self) {
+        let entries = self.map.drain().collect::<Vec<_>>();
+        // the map keeps its allocation, so there is enough room
+        self.map.extend(entries);
+    }
+    /// Returns `true` if the set contains no elements, including unused ones.
+    pub fn is_empty(&self) -> bool {
+        self.arena.is_empty()
+    }
+    /// Tries to reserve capacity for at least `additional` more elements.
+    ///
+    /// In contrast to [`reserve()`](#method.reserve) an error is returned if
+    /// the capacity would overflow or the map of the set fails to allocate.
+    ///
+    /// _Note:_ The arena has no fallible allocation. Hence, only an overflow
+    /// of its capacity is detected. A failing allocation of the arena still
+    /// aborts.
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        let required = self
+            .arena
+            .len()
+            .checked_add(additional)
+            .ok_or(TryReserveError::CapacityOverflow)?;
+        let arena_bytes = required
+            .checked_mul(std::mem::size_of::<Entry<T, C>>())
+            .ok_or(TryReserveError::CapacityOverflow)?;
+        if arena_bytes > isize::MAX as usize {
+            return Err(TryReserveError::CapacityOverflow);
+        }
+
+        self.map.try_reserve(additional)?;
+        let arena_capacity = self.arena.capacity();
+        if required > arena_capacity {
+            self.arena.reserve(required - arena_capacity);
+        }
+        Ok(())
+    }
+    /// Reserves capacity for `expected_distinct` new elements, e.g. before
+    /// interning many elements.
+    ///
+    /// This is the same as [`reserve()`](#method.reserve). If the number of
+    /// distinct new elements is known, reserving once makes a loop of, e.g.,
+    /// [`get_or_insert()`](#method.get_or_insert) free of reallocations.
+    pub fn reserve_for_distinct(&mut self, expected_distinct: usize) {
+        self.reserve(expected_distinct);
+    }
+    /// Shrinks the capacity of the set as much as possible.
+    ///
+    /// See [`shrink_to()`](#method.shrink_to).
+    pub fn shrink_to_fit(&mut self) {
+        self.map.shrink_to_fit();
+    }
+    /// Shrinks the capacity of the set towards `min_capacity` but never below
+    /// its length.
+    ///
+    /// Only the map from elements to indices is shrunk. The arena can not
+    /// shrink, its slots are kept for reuse by future insertions.
+    pub fn shrink_to(&mut self, min_capacity: usize) {
+        self.map.shrink_to(min_capacity);
+    }
     /// Get the usage count of an element by hash.
+    ///
+    /// If an index of the element is at hand use
+    /// [`get_cnt_by_index()`](#method.get_cnt_by_index) instead, it returns
+    /// the same count without hashing.
     pub fn get_cnt<Q>(&self, elem: &Q) -> Option<usize>
     where
         T: Borrow<Q>,
         Q: ?Sized + Hash + Eq,
     {
+        // nothing to find, so skip hashing the possibly expensive element
+        if self.arena.is_empty() {
+            return None;
+        }
         let idx = self.map.get_key_value(elem.wrap()).map(|(_, idx)| *idx)?;
         let entry = &self.arena[idx];
         Some(entry.cnt())
     }
+    /// Get the usage count of an element by index.
+    ///
+    /// As the index can be from another set this operation is fallible.
+    pub fn get_cnt_by_index(&self, idx: &RcIndex<C>) -> Option<usize> {
+        let entry = self.arena.get(idx.inner)?;
+        Some(entry.cnt())
+    }
+    /// Returns `true` if the element is in the set, including unused ones.
+    pub fn contains<Q>(&self, elem: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        if self.arena.is_empty() {
+            return false;
+        }
+        self.map.contains_key(elem.wrap())
+    }
+    /// Returns `true` if all `keys` are in the set.
+    pub fn contains_all<'q, Q>(&self, keys: impl IntoIterator<Item = &'q Q>) -> bool
+    where
+        T: Borrow<Q>,
+        Q: 'q + ?Sized + Hash + Eq,
+    {
+        keys.into_iter().all(|key| self.contains(key))
+    }
+    /// Returns `true` if the sets have no elements in common.
+    ///
+    /// The elements of the smaller set are looked up in the larger one.
+    pub fn is_disjoint<D, R>(&self, other: &IndexedHashSet<T, D, R>) -> bool
+    where
+        D: Counter,
+        R: BuildHasher,
+    {
+        if self.len() <= other.len() {
+            self.arena.iter().all(|(_, e)| !other.contains(e.elem()))
+        } else {
+            other.arena.iter().all(|(_, e)| !self.contains(e.elem()))
+        }
+    }
     /// Get a reference to the stored element by hash.
     pub fn get_ref_by_hash<'a, Q>(&'a self, elem: &Q) -> Option<&'a T>
     where
         T: Borrow<Q>,
         Q: ?Sized + Hash + Eq,
     {
+        if self.arena.is_empty() {
+            return None;
+        }
         // points to the same entry, no need for a lookup in the arena
         self.map.get_key_value(elem.wrap()).map(|(k, _)| k.as_ref())
     }
+    /// Get a reference to the stored element, like `HashSet::get()`.
+    ///
+    /// This is the same as [`get_ref_by_hash()`](#method.get_ref_by_hash). To
+    /// access elements without hashing use an index, see
+    /// [`get_index_by_hash()`](#method.get_index_by_hash) and
+    /// [`get_ref_by_index()`](#method.get_ref_by_index).
+    pub fn get<Q>(&self, elem: &Q) -> Option<&T>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.get_ref_by_hash(elem)
+    }
+    /// Get the borrowed form of the stored element, e.g. a canonical `&str`
+    /// of a set of `String`s.
+    pub fn get_key<Q>(&self, key: &Q) -> Option<&Q>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.get(key).map(|elem| elem.borrow())
+    }
+    /// Get a mutable reference to the stored element by hash.
+    ///
+    /// _Note:_ The element must not be changed in a way that changes its hash
+    /// or equality. Otherwise, the element can no longer be found by hash.
+    /// Only parts that are irrelevant for `Hash` and `Eq` may be mutated.
+    pub fn get_mut_by_hash<Q>(&mut self, elem: &Q) -> Option<&mut T>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        if self.arena.is_empty() {
+            return None;
+        }
+        // copy the index out to end the borrow of the map
+        let a_idx = *self.map.get(elem.wrap())?;
+        Some(self.arena[a_idx].elem.as_mut())
+    }
     /// Get the index of the stored element by hash.
-    pub fn get_index_by_hash<'a, Q>(&'a self, elem: &Q) -> Option<RcIndex>
+    ///
+    /// The usage count of the entry is incremented before the index is
+    /// returned. Hence, even an unused entry is kept alive by the returned
+    /// index and is not dropped by a subsequent
+    /// [`drop_unused()`](#method.drop_unused).
+    ///
+    /// _Note:_ Just as for all lookups by hash `elem` must be a type `T`
+    /// borrows as directly, chains of `Borrow` are not followed. Convert other
+    /// types up front:
+    ///
+    /// ```
+    /// # use indexed_hash_set::IndexedHashSet;
+    /// use std::path::{Path, PathBuf};
+    ///
+    /// let mut set = IndexedHashSet::<PathBuf>::new();
+    /// let idx = set.insert(PathBuf::from("/etc/hosts")).unwrap();
+    /// // `PathBuf: Borrow<Path>`
+    /// assert_eq!(set.get_index_by_hash(Path::new("/etc/hosts")), Some(idx));
+    /// // there is no `PathBuf: Borrow<str>`, so convert a `&str` to a `&Path`
+    /// let name = "/etc/hosts";
+    /// assert!(set.contains(Path::new(name)));
+    /// ```
+    pub fn get_index_by_hash<Q>(&self, elem: &Q) -> Option<RcIndex<C>>
     where
         T: Borrow<Q>,
         Q: ?Sized + Hash + Eq,
     {
+        if self.arena.is_empty() {
+            return None;
+        }
         let a_idx = self.map.get(elem.wrap())?;
         Some(self.aidx_to_rcidx(*a_idx))
     }
+    /// Get the stored element, a new index to it and its usage count by hash
+    /// with a single lookup.
+    ///
+    /// The count is read after the returned index was created, i.e. it
+    /// includes the returned index.
+    pub fn lookup_full<Q>(&self, elem: &Q) -> Option<(&T, RcIndex<C>, usize)>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        if self.arena.is_empty() {
+            return None;
+        }
+        let a_idx = *self.map.get(elem.wrap())?;
+        let idx = self.aidx_to_rcidx(a_idx);
+        let entry = &self.arena[a_idx];
+        Some((entry.elem(), idx, entry.cnt()))
+    }
+    /// Calls `f` with the stored element and a function creating an index to
+    /// it, both found by a single lookup.
+    ///
+    /// The index is only created, i.e. the usage count only incremented, if
+    /// `f` calls the given function. Returns `None` without calling `f` if the
+    /// element is not in the set.
+    pub fn with_entry<Q, R, F>(&self, elem: &Q, f: F) -> Option<R>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+        F: FnOnce(&T, &dyn Fn() -> RcIndex<C>) -> R,
+    {
+        if self.arena.is_empty() {
+            return None;
+        }
+        let a_idx = *self.map.get(elem.wrap())?;
+        Some(f(self.arena[a_idx].elem(), &|| self.aidx_to_rcidx(a_idx)))
+    }
+    /// Get the indices of all present `keys` at once.
+    ///
+    /// Absent keys are skipped. In contrast to [`pin()`](#method.pin) the
+    /// entries are not pinned persistently, they are kept alive as long as
+    /// the returned indices are held.
+    pub fn pin_group<'q, Q>(&self, keys: impl IntoIterator<Item = &'q Q>) -> Vec<RcIndex<C>>
+    where
+        T: Borrow<Q>,
+        Q: 'q + ?Sized + Hash + Eq,
+    {
+        keys.into_iter()
+            .filter_map(|key| self.get_index_by_hash(key))
+            .collect()
+    }
+    /// Get the index of an element from a reference into this set.
+    ///
+    /// `elem_ref` must be obtained from this set, e.g. by
+    /// [`get_ref_by_hash()`](#method.get_ref_by_hash). The entry is
+    /// identified by the address of the stored element, so a reference to an
+    /// equal element that is not stored in this set yields `None`.
+    ///
+    /// _Note:_ The address alone does not reveal the slot of the entry in the
+    /// arena. Hence, the element is still hashed to find its entry in the map.
+    /// However, no equality comparisons are performed.
+    pub fn index_of_ref(&self, elem_ref: &T) -> Option<RcIndex<C>> {
+        let a_idx = self.find_ptr(elem_ref)?;
+        Some(self.aidx_to_rcidx(a_idx))
+    }
+    /// Returns `true` if `elem_ref` points to an element stored in this set.
+    ///
+    /// A reference to an equal element that is not stored in this set yields
+    /// `false`. Just as [`index_of_ref()`](#method.index_of_ref) the element
+    /// is hashed but not compared.
+    pub fn contains_value_ptr(&self, elem_ref: &T) -> bool {
+        self.find_ptr(elem_ref).is_some()
+    }
+    /// Get a lazy index of the stored element by hash.
+    ///
+    /// In contrast to [`get_index_by_hash()`](#method.get_index_by_hash) the
+    /// usage count is not incremented until the index is
+    /// [pinned](./struct.LazyIndex.html#method.pin). Hence, the returned
+    /// index does not keep the entry alive.
+    pub fn lazy_index_by_hash<Q>(&self, elem: &Q) -> Option<LazyIndex<C>>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        if self.arena.is_empty() {
+            return None;
+        }
+        let a_idx = *self.map.get(elem.wrap())?;
+        Some(LazyIndex {
+            inner: a_idx,
+            cnt: C::downgrade(&self.arena[a_idx].usage_cnt),
+        })
+    }
+    /// Get the index of the stored element by hash together with the state
+    /// of the entry before the lookup.
+    ///
+    /// In contrast to [`get_index_by_hash()`](#method.get_index_by_hash) an
+    /// unused entry that awaits being dropped is reported as
+    /// [`Dead`](./enum.IndexState.html#variant.Dead). The returned index
+    /// resurrects such an entry, so drop it to leave the entry unused.
+    pub fn index_state<Q>(&self, elem: &Q) -> IndexState<C>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        if self.arena.is_empty() {
+            return IndexState::Absent;
+        }
+        match self.map.get(elem.wrap()) {
+            None => IndexState::Absent,
+            Some(a_idx) if self.arena[*a_idx].is_used() => {
+                IndexState::Live(self.aidx_to_rcidx(*a_idx))
+            }
+            Some(a_idx) => IndexState::Dead(self.aidx_to_rcidx(*a_idx)),
+        }
+    }
+    /// Get an observer of the usage count of the stored element by hash.
+    ///
+    /// Just as a [`LazyIndex`](./struct.LazyIndex.html) the observer does not
+    /// keep the entry alive.
+    pub fn count_observer<Q>(&self, elem: &Q) -> Option<CountObserver<C>>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        if self.arena.is_empty() {
+            return None;
+        }
+        let a_idx = *self.map.get(elem.wrap())?;
+        Some(CountObserver {
+            cnt: C::downgrade(&self.arena[a_idx].usage_cnt),
+        })
+    }
+    /// Get the indices of several stored elements by hash.
+    ///
+    /// The result has the same order as `keys`. Absent keys yield `None`.
+    /// Holding the result keeps all looked up entries alive.
+    pub fn get_indices<Q>(&self, keys: &[&Q]) -> Vec<Option<RcIndex<C>>>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        keys.iter()
+            .map(|key| self.get_index_by_hash(*key))
+            .collect()
+    }
     /// Get a reference to the stored element by index.
     ///
     /// As the index can be from another `IndexedHashSet` this operation is
@@ -117,10 +745,131 @@ where
     /// can be used, e.g. `set[&rc_idx]`. However, this may panic with a
     /// foreign `RcIndex`.
     //#impl-Index<%26'a RcIndex>
-    pub fn get_ref_by_index<'a>(&'a self, idx: &RcIndex) -> Option<&'a T> {
+    pub fn get_ref_by_index<'a>(&'a self, idx: &RcIndex<C>) -> Option<&'a T> {
         let entry = self.arena.get(idx.inner)?;
         Some(entry.elem.as_ref())
     }
+    /// Get a reference to the stored element by index whose address is
+    /// stable.
+    ///
+    /// This is the same as [`get_ref_by_index()`](#method.get_ref_by_index)
+    /// but documents a guarantee: The elements are boxed, so the address of
+    /// an element never changes while its entry is in the set, even if the
+    /// set grows. As the held `idx` keeps the entry from being dropped as
+    /// unused, the address can be relied upon, e.g. for raw pointers, until
+    /// the entry is removed explicitly, e.g. by
+    /// [`remove_by_index()`](#method.remove_by_index).
+    pub fn stable_ref_by_index(&self, idx: &RcIndex<C>) -> Option<&T> {
+        self.get_ref_by_index(idx)
+    }
+    /// Get a raw pointer to the stored element by index, e.g. for FFI.
+    ///
+    /// The pointer is valid as long as the entry is in the set, see
+    /// [`stable_ref_by_index()`](#method.stable_ref_by_index). Holding `idx`
+    /// or another index to the entry keeps it from being dropped as unused.
+    /// However, the pointer is invalidated if the entry is removed explicitly
+    /// or the set is dropped.
+    pub fn raw_ptr_by_index(&self, idx: &RcIndex<C>) -> Option<*const T> {
+        self.stable_ref_by_index(idx).map(|elem| elem as *const T)
+    }
+    /// Get a reference to the stored element by raw arena index.
+    ///
+    /// This is the lowest-level accessor. The usage count is neither checked
+    /// nor changed. The raw index must be from this set, e.g. from
+    /// [`iter_raw()`](#method.iter_raw) or
+    /// [`RcIndex::into_raw()`](./struct.RcIndex.html#method.into_raw).
+    /// Otherwise, an arbitrary element may be returned.
+    pub fn get_ref_by_raw(&self, raw: AIndex) -> Option<&T> {
+        self.arena.get(raw).map(|e| e.elem())
+    }
+    /// Get a reference to the stored element by index or the reason why
+    /// this is not possible.
+    ///
+    /// In contrast to [`get_ref_by_index()`](#method.get_ref_by_index) this
+    /// also detects foreign indices that happen to point to an entry of this
+    /// set. However, not every foreign index is detectable, see
+    /// [`IndexError`](./enum.IndexError.html).
+    pub fn get_ref_by_index_result(&self, idx: &RcIndex<C>) -> Result<&T, IndexError> {
+        let (slot, _) = idx.inner.into_raw_parts();
+        match self.arena.get_unknown_gen(slot) {
+            Some((entry, a_idx)) if a_idx == idx.inner => {
+                if C::ptr_eq(&entry.usage_cnt, &idx.cnt) {
+                    Ok(entry.elem())
+                } else {
+                    Err(IndexError::Foreign)
+                }
+            }
+            Some(_) => Err(IndexError::Stale),
+            None if slot < self.arena.capacity() => Err(IndexError::Collected),
+            None => Err(IndexError::Foreign),
+        }
+    }
+    /// Get another index to the entry of `idx`.
+    ///
+    /// In contrast to `RcIndex::clone()` the index is validated against the
+    /// set, see [`get_ref_by_index_result()`](#method.get_ref_by_index_result).
+    /// Returns `None` if `idx` can not be resolved by this set, i.e. stale
+    /// indices are not duplicated. Note that neither method clones the
+    /// element itself.
+    pub fn new_index(&self, idx: &RcIndex<C>) -> Option<RcIndex<C>> {
+        self.get_ref_by_index_result(idx).ok()?;
+        Some(self.aidx_to_rcidx(idx.inner))
+    }
+    /// Updates a stale index in place to the entry of `elem`.
+    ///
+    /// An index becomes stale if its entry is removed, e.g. by
+    /// [`remove_by_hash()`](#method.remove_by_hash), even if an equal element
+    /// is inserted again later. A stale index no longer knows its element.
+    /// Hence, the element is passed as `elem` and looked up by hash. If it is
+    /// found `idx` is replaced by a new index to its entry, i.e. the usage
+    /// moves to the current entry.
+    ///
+    /// Returns `true` if `idx` was updated and `false` if it was still valid
+    /// or `elem` is not in the set.
+    ///
+    /// _Note:_ A stale index does not keep the current entry of `elem` alive.
+    /// Hence, indices must be refreshed before the unused entries are dropped,
+    /// e.g. by [`drop_unused()`](#method.drop_unused), and not afterwards.
+    pub fn refresh<Q>(&self, idx: &mut RcIndex<C>, elem: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        if self.get_ref_by_index_result(idx).is_ok() {
+            return false;
+        }
+        match self.get_index_by_hash(elem) {
+            Some(fresh) => {
+                *idx = fresh;
+                true
+            }
+            None => false,
+        }
+    }
+    /// Compares the elements of two indices.
+    ///
+    /// Returns `None` if one of the indices can not be resolved by this set.
+    /// This allows to sort indices by their elements, e.g.
+    /// `indices.sort_by(|a, b| set.cmp_indices(a, b).unwrap())`.
+    pub fn cmp_indices(&self, a: &RcIndex<C>, b: &RcIndex<C>) -> Option<Ordering>
+    where
+        T: Ord,
+    {
+        let a = self.get_ref_by_index(a)?;
+        let b = self.get_ref_by_index(b)?;
+        Some(a.cmp(b))
+    }
+    /// Get a clone of the stored element by index.
+    ///
+    /// In contrast to [`get_ref_by_index()`](#method.get_ref_by_index) the
+    /// result does not borrow the set. Thus, the set can be mutated while the
+    /// clone is held.
+    pub fn get_cloned_by_index(&self, idx: &RcIndex<C>) -> Option<T>
+    where
+        T: Clone,
+    {
+        self.get_ref_by_index(idx).cloned()
+    }
     /// Insert a new element into the set.
     ///
     /// If the element is already in the set `None` is returned else the index
@@ -129,185 +878,3820 @@ where
     /// _Note:_ The returned `RcIndex` is the initial usage of the entry. If it
     /// is dropped without cloning the `usage_cnt` goes to zero and the new
     /// element is dropped on the next [`drop_unused()`](#method.drop_unused)!
+    ///
+    /// # Panics
+    ///
+    /// This panics if the set was created [`with_max_entries()`] and is still
+    /// full after dropping the unused entries. Use [`try_insert()`] to handle
+    /// this case.
+    ///
+    /// [`with_max_entries()`]: #method.with_max_entries
+    /// [`try_insert()`]: #method.try_insert
     #[must_use = "If not stored usage count of the new element goes to zero."]
-    pub fn insert(&mut self, elem: T) -> Option<RcIndex> {
-        if self.map.get(elem.wrap()).is_some() {
+    pub fn insert(&mut self, elem: T) -> Option<RcIndex<C>> {
+        let hash = self.hash_of(&elem);
+        if self.find_hashed(hash, &elem).is_some() {
+            trace_event!(tracing::Level::DEBUG, len = self.len(), "duplicate insert");
             return None;
         }
 
-        Some(self.insert_unchecked(elem))
+        Some(self.insert_unchecked(hash, elem))
     }
-    /// Gets the index of the element in the set if present. If not the element
-    /// is inserted and the new index is returned.
-    pub fn get_or_insert(&mut self, elem: &T) -> RcIndex
-    where
-        T: Clone,
-    {
-        if let Some(a_idx) = self.map.get(elem.wrap()) {
-            self.aidx_to_rcidx(*a_idx)
-        } else {
-            self.insert_unchecked(elem.clone())
+    /// Insert a new element whose hash was already computed.
+    ///
+    /// Apart from skipping the hashing of `elem` this behaves like
+    /// [`insert()`](#method.insert). Equality to present elements is still
+    /// checked.
+    ///
+    /// # Correctness
+    ///
+    /// `hash` must be the hash of `elem` computed by
+    /// [`hash_of()`](#method.hash_of) of this set. Otherwise, the element is
+    /// stored under a wrong hash and may neither be found by lookups nor
+    /// detected as duplicate. This does not cause undefined behavior.
+    ///
+    /// # Panics
+    ///
+    /// Just as [`insert()`](#method.insert) this panics if a set with a
+    /// maximum number of entries is full.
+    #[must_use = "If not stored usage count of the new element goes to zero."]
+    pub fn insert_prehashed(&mut self, hash: u64, elem: T) -> Option<RcIndex<C>> {
+        if self.find_hashed(hash, &elem).is_some() {
+            return None;
         }
+
+        Some(self.insert_unchecked(hash, elem))
     }
-    /// Unconditionally inserts the element.
+    /// Insert a new element into a set with a maximum number of entries.
     ///
-    /// If not checked carefully this may violate the `IndexedHashSet`'s
+    /// Just as [`insert()`](#method.insert) `Ok(None)` is returned if the
+    /// element is already in the set. If the set is full the unused entries
+    /// are dropped to make room. If the set is still full the element is
+    /// returned in the error.
+    ///
+    /// For sets without a maximum this never fails.
+    pub fn try_insert(&mut self, elem: T) -> Result<Option<RcIndex<C>>, CapacityError<T>> {
+        let hash = self.hash_of(&elem);
+        if self.find_hashed(hash, &elem).is_some() {
+            return Ok(None);
+        }
+        if !self.make_room() {
+            return Err(CapacityError { elem });
+        }
+
+        Ok(Some(self.insert_unchecked(hash, elem)))
+    }
+    /// Replaces the stored element that is equal to `elem` and returns it.
+    ///
+    /// This is useful if the equality of `T` considers only parts of it. The
+    /// entry, including its usage count, is kept. Hence, existing indices
+    /// resolve to `elem` afterwards.
+    ///
+    /// If no equal element is in the set `elem` is inserted and `None` is
+    /// returned. As no index is returned the new entry is unused.
+    ///
+    /// # Panics
+    ///
+    /// Just as [`insert()`](#method.insert) this panics if a set with a
+    /// maximum number of entries is full and `elem` is new.
+    pub fn replace(&mut self, elem: T) -> Option<T> {
+        let hash = self.hash_of(&elem);
+        match self.find_hashed(hash, &elem) {
+            // written in place through the pointer the key in the map is built
+            // from, so the key stays valid
+            Some(a_idx) => Some(std::mem::replace(self.arena[a_idx].elem.as_mut(), elem)),
+            None => {
+                let _ = self.insert_unchecked(hash, elem);
+                None
+            }
+        }
+    }
+    /// Insert an element that is kept regardless of indices.
+    ///
+    /// In contrast to [`insert()`](#method.insert) no index is needed to keep
+    /// the element in the set. The entry is [pinned](#method.pin), i.e. it is
+    /// never dropped as unused, only by
+    /// [`remove_by_hash()`](#method.remove_by_hash). If an equal element is
+    /// already in the set that one is pinned instead.
+    ///
+    /// Just as `HashSet::insert()` this returns `true` if the element was
+    /// newly inserted.
+    ///
+    /// # Panics
+    ///
+    /// Just as [`insert()`](#method.insert) this panics if a set with a
+    /// maximum number of entries is full.
+    pub fn insert_persistent(&mut self, elem: T) -> bool {
+        let hash = self.hash_of(&elem);
+        let (a_idx, new) = match self.find_hashed(hash, &elem) {
+            Some(a_idx) => (a_idx, false),
+            None => (self.insert_unchecked(hash, elem).inner, true),
+        };
+        self.arena[a_idx].pinned = true;
+        new
+    }
+    /// Remove the entry of an index, regardless of its usage count, and
+    /// return its element.
+    ///
+    /// Returns `None` if the index can not be resolved by this set, see
+    /// [`get_ref_by_index_result()`](#method.get_ref_by_index_result). Other
+    /// outstanding indices to the removed entry can no longer be used to
+    /// access the set.
+    pub fn remove_by_index(&mut self, idx: &RcIndex<C>) -> Option<T> {
+        self.get_ref_by_index_result(idx).ok()?;
+        let entry = self.arena.remove(idx.inner)?;
+        // the element is still boxed, so the self-reference is still valid
+        self.map.remove(entry.elem().wrap());
+        Some(entry.elem.into_inner())
+    }
+    /// Pin an element by hash, i.e. make it persistent.
+    ///
+    /// A pinned entry is never dropped as unused, even if its usage count is
+    /// zero. Returns `false` if the element is not in the set.
+    pub fn pin<Q>(&mut self, elem: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.set_pinned(elem, true)
+    }
+    /// Unpin an element by hash.
+    ///
+    /// Afterwards, the entry is dropped as unused again once its usage count
+    /// is zero. Returns `false` if the element is not in the set.
+    pub fn unpin<Q>(&mut self, elem: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.set_pinned(elem, false)
+    }
+    /// Sets the pinned flag of the entry of `elem` if present.
+    fn set_pinned<Q>(&mut self, elem: &Q, pinned: bool) -> bool
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        if self.arena.is_empty() {
+            return false;
+        }
+        let a_idx = match self.map.get(elem.wrap()) {
+            Some(a_idx) => *a_idx,
+            None => return false,
+        };
+        let entry = &mut self.arena[a_idx];
+        entry.pinned = pinned;
+        if !entry.is_used() {
+            // no index will raise the flag for this entry
+            C::raise(&self.dirty);
+        }
+        true
+    }
+    /// Remove an element by hash, regardless of its usage count.
+    ///
+    /// Returns `true` if the element was in the set. Outstanding indices to
+    /// the removed entry can no longer be used to access the set.
+    pub fn remove_by_hash<Q>(&mut self, elem: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        if self.arena.is_empty() {
+            return false;
+        }
+        match self.map.remove(elem.wrap()) {
+            Some(a_idx) => {
+                self.arena.remove(a_idx);
+                true
+            }
+            None => false,
+        }
+    }
+    /// Remove an element by hash only if it is unused.
+    ///
+    /// In contrast to [`drop_unused()`](#method.drop_unused) no other entries
+    /// are scanned. Returns `true` if the element was removed. Used and
+    /// persistent elements are kept.
+    pub fn remove_if_unused<Q>(&mut self, elem: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        if self.arena.is_empty() {
+            return false;
+        }
+        match self.map.raw_entry_mut().from_key(elem.wrap()) {
+            RawEntryMut::Occupied(occupied) if !self.arena[*occupied.get()].is_used() => {
+                let a_idx = occupied.remove();
+                self.arena.remove(a_idx);
+                true
+            }
+            _ => false,
+        }
+    }
+    /// Gets the index of the element in the set if present. If not the element
+    /// is inserted and the new index is returned.
+    ///
+    /// The element is hashed only once for both the lookup and the insertion.
+    ///
+    /// # Panics
+    ///
+    /// Just as [`insert()`](#method.insert) this panics if a set with a
+    /// maximum number of entries is full.
+    pub fn get_or_insert(&mut self, elem: &T) -> RcIndex<C>
+    where
+        T: Clone,
+    {
+        let hash = self.hash_of(elem);
+        if let Some(a_idx) = self.find_hashed(hash, elem) {
+            self.aidx_to_rcidx(a_idx)
+        } else {
+            // the clone is equal to `elem` and, therefore, has the same hash
+            self.insert_unchecked(hash, elem.clone())
+        }
+    }
+    /// Gets the index of the element if present or tries to insert it.
+    ///
+    /// Before the element is inserted room for it is reserved via
+    /// [`try_reserve()`](#method.try_reserve). If this fails the element is
+    /// returned together with the error. If the element is present nothing
+    /// is allocated.
+    ///
+    /// # Panics
+    ///
+    /// Just as [`insert()`](#method.insert) this panics if a set with a
+    /// maximum number of entries is full.
+    pub fn try_get_or_insert(&mut self, elem: T) -> Result<RcIndex<C>, (T, TryReserveError)> {
+        self.try_get_or_insert_with(elem, |set| set.try_reserve(1))
+    }
+    /// Gets the index of the element if present or inserts it if `reserve`
+    /// succeeds.
+    ///
+    /// This is [`try_get_or_insert()`](#method.try_get_or_insert) with the
+    /// reservation passed in, e.g. to simulate a failing allocation.
+    fn try_get_or_insert_with<F>(
+        &mut self,
+        elem: T,
+        reserve: F,
+    ) -> Result<RcIndex<C>, (T, TryReserveError)>
+    where
+        F: FnOnce(&mut Self) -> Result<(), TryReserveError>,
+    {
+        let hash = self.hash_of(&elem);
+        if let Some(a_idx) = self.find_hashed(hash, &elem) {
+            return Ok(self.aidx_to_rcidx(a_idx));
+        }
+        if let Err(err) = reserve(self) {
+            return Err((elem, err));
+        }
+        Ok(self.insert_unchecked(hash, elem))
+    }
+    /// Gets the index of the element equal to `key` if present. If not the
+    /// element produced by `to_owned` is inserted and its index is returned.
+    ///
+    /// `to_owned` is only called if `key` is absent, e.g. to turn a `&str`
+    /// into a `String` only when needed. The key is hashed only once.
+    ///
+    /// # Panics
+    ///
+    /// The produced element must be equal to `key`, which is checked in
+    /// debug builds. Besides, just as [`insert()`](#method.insert) this
+    /// panics if a set with a maximum number of entries is full.
+    pub fn get_or_insert_by<Q, F>(&mut self, key: &Q, to_owned: F) -> RcIndex<C>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+        F: FnOnce() -> T,
+    {
+        // equal to `key`, the element has the same hash
+        let hash = self.hash_of(key);
+        let found = self
+            .map
+            .raw_entry()
+            .from_hash(hash, |k| k.as_ref().borrow() == key)
+            .map(|(_, a_idx)| *a_idx);
+        match found {
+            Some(a_idx) => self.aidx_to_rcidx(a_idx),
+            None => {
+                let elem = to_owned();
+                debug_assert!(elem.borrow() == key, "produced element differs from key");
+                self.insert_unchecked(hash, elem)
+            }
+        }
+    }
+    /// Gets the entry of the element for in-place manipulation.
+    ///
+    /// The element is hashed only once, see [`entry`](./entry/index.html).
+    pub fn entry(&mut self, elem: T) -> entry::Entry<'_, T, C, S> {
+        let hash = self.hash_of(&elem);
+        match self.find_hashed(hash, &elem) {
+            Some(a_idx) => entry::Entry::Occupied(entry::OccupiedEntry { set: self, a_idx }),
+            None => entry::Entry::Vacant(entry::VacantEntry {
+                set: self,
+                hash,
+                elem,
+            }),
+        }
+    }
+    /// Inserts the element if not present and tells which case happened.
+    ///
+    /// In both cases an index to the entry is returned. If an equal element
+    /// was already present `elem` is dropped.
+    ///
+    /// # Panics
+    ///
+    /// Just as [`insert()`](#method.insert) this panics if a set with a
+    /// maximum number of entries is full.
+    pub fn classify_insert(&mut self, elem: T) -> Inserted<C> {
+        let hash = self.hash_of(&elem);
+        if let Some(a_idx) = self.find_hashed(hash, &elem) {
+            Inserted::Existing(self.aidx_to_rcidx(a_idx))
+        } else {
+            Inserted::New(self.insert_unchecked(hash, elem))
+        }
+    }
+    /// Interns the element, i.e. gets the stored equal element or inserts it.
+    ///
+    /// Returns an index and a reference to the canonical instance of the
+    /// element. All equal elements are interned to the same instance, i.e.
+    /// the returned references point to the same address. If an equal element
+    /// was already present `elem` is dropped.
+    ///
+    /// # Panics
+    ///
+    /// Just as [`insert()`](#method.insert) this panics if a set with a
+    /// maximum number of entries is full.
+    pub fn intern(&mut self, elem: T) -> (RcIndex<C>, &T) {
+        let idx = self.get_or_insert_owned(elem);
+        let elem = self.arena[idx.inner].elem();
+        (idx, elem)
+    }
+    /// Gets the index and a reference to the element if present or inserts
+    /// it.
+    ///
+    /// The reference borrows the set while the index keeps the entry alive
+    /// beyond that borrow. This is the same as [`intern()`](#method.intern).
+    ///
+    /// # Panics
+    ///
+    /// Just as [`insert()`](#method.insert) this panics if a set with a
+    /// maximum number of entries is full.
+    pub fn get_or_insert_entry(&mut self, elem: T) -> (RcIndex<C>, &T) {
+        self.intern(elem)
+    }
+    /// Gets the index of the element if present or inserts it.
+    ///
+    /// In contrast to [`get_or_insert()`](#method.get_or_insert) this takes
+    /// the element by value and, therefore, needs no clone.
+    fn get_or_insert_owned(&mut self, elem: T) -> RcIndex<C> {
+        let hash = self.hash_of(&elem);
+        if let Some(a_idx) = self.find_hashed(hash, &elem) {
+            self.aidx_to_rcidx(a_idx)
+        } else {
+            self.insert_unchecked(hash, elem)
+        }
+    }
+    /// Unconditionally inserts the element with its precomputed `hash`.
+    ///
+    /// If not checked carefully this may violate the `IndexedHashSet`'s
     /// contract that elements are distinct as the arena doesn't have the
-    /// properties of a set.
-    fn insert_unchecked(&mut self, elem: T) -> RcIndex {
-        let entry = Entry::new(elem);
+    /// properties of a set. In addition, `hash` must be the result of
+    /// [`hash_of()`](#method.hash_of) for `elem`.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the set is full, see [`make_room()`](#method.make_room).
+    fn insert_unchecked(&mut self, hash: u64, elem: T) -> RcIndex<C> {
+        assert!(
+            self.make_room(),
+            "IndexedHashSet reached its maximum number of entries"
+        );
+
+        let mut entry = Entry::new(elem);
+        entry.touched.store(self.epoch, atomic::Ordering::Relaxed);
+        entry.seq = self.next_seq;
         let cnt_handle = entry.cnt_handle();
-        let inner_ref = InternalRef::from_ref(entry.elem());
+        let inner_ref = entry.elem.internal_ref();
+
+        let a_idx = self.arena.insert(entry);
+        // growing the map rehashes the other elements which may panic. Then,
+        // the new entry must not stay in the arena without a key in the map.
+        let guard = RemoveOnUnwind {
+            arena: &mut self.arena,
+            a_idx,
+        };
+        match self.map.raw_entry_mut().from_hash(hash, |_| false) {
+            RawEntryMut::Vacant(vacant) => {
+                vacant.insert_hashed_nocheck(hash, inner_ref, a_idx);
+            }
+            RawEntryMut::Occupied(_) => unreachable!("never matched"),
+        }
+        std::mem::forget(guard);
+        // only consume the sequence number and count the insertion once it is
+        // committed
+        self.next_seq += 1;
+        self.metrics.total_inserts += 1;
+        trace_event!(tracing::Level::DEBUG, len = self.len(), "new insert");
+
+        RcIndex::new(a_idx, cnt_handle, self.dirty.clone())
+    }
+    /// Ensures there is room for another entry if the number of entries is
+    /// limited.
+    ///
+    /// If the set is full the unused entries are dropped. Returns `false` if
+    /// the set is still full.
+    fn make_room(&mut self) -> bool {
+        match self.max_entries {
+            Some(max) if self.arena.len() >= max => {
+                self.drop_unused();
+                self.arena.len() < max
+            }
+            _ => true,
+        }
+    }
+    /// Looks up the arena index of `elem` using its precomputed `hash`.
+    fn find_hashed(&self, hash: u64, elem: &T) -> Option<AIndex> {
+        self.map
+            .raw_entry()
+            .from_hash(hash, |k| k.as_ref() == elem)
+            .map(|(_, a_idx)| *a_idx)
+    }
+    /// Looks up the arena index of the element `elem_ref` points to.
+    fn find_ptr(&self, elem_ref: &T) -> Option<AIndex> {
+        if self.arena.is_empty() {
+            return None;
+        }
+        let hash = self.hash_of(elem_ref);
+        self.map
+            .raw_entry()
+            .from_hash(hash, |k| std::ptr::eq(k.as_ref(), elem_ref))
+            .map(|(_, a_idx)| *a_idx)
+    }
+    /// Computes the hash of an element like the set does internally.
+    ///
+    /// The hash is specific to this set. It can be used for
+    /// [`insert_prehashed()`](#method.insert_prehashed).
+    pub fn hash_of<Q>(&self, elem: &Q) -> u64
+    where
+        Q: ?Sized + Hash,
+    {
+        self.map.hasher().hash_one(elem)
+    }
+    /// Moves all elements of `other` into `self` that are not already in it.
+    ///
+    /// The entries of `self` and their indices are untouched. The indices of
+    /// newly added elements are dropped, i.e. their usage counts are zero.
+    /// Indices into `other` become useless as `other` is consumed.
+    ///
+    /// # Panics
+    ///
+    /// Just as [`insert()`](#method.insert) this panics if a set with a
+    /// maximum number of entries is full.
+    pub fn merge<D: Counter, R>(&mut self, other: IndexedHashSet<T, D, R>) {
+        self.extend(other);
+    }
+    /// Inserts the elements and reports for each if it was new.
+    ///
+    /// The result has the same order as `iter`. Just as for the `Extend`
+    /// implementation the indices of new elements are dropped, i.e. they are
+    /// unused.
+    ///
+    /// # Panics
+    ///
+    /// Just as [`insert()`](#method.insert) this panics if a set with a
+    /// maximum number of entries is full.
+    pub fn extend_reporting<I: IntoIterator<Item = T>>(&mut self, iter: I) -> Vec<bool> {
+        iter.into_iter()
+            .map(|elem| self.insert(elem).is_some())
+            .collect()
+    }
+    /// Inserts the elements and returns an index for each of them.
+    ///
+    /// The result has the same order and length as `iter`. Duplicates, within
+    /// `iter` or with the set, yield an index to the existing entry. Hence, all
+    /// elements are kept alive by the result. Capacity is reserved once based
+    /// on the size hint of `iter`.
+    ///
+    /// # Panics
+    ///
+    /// Just as [`insert()`](#method.insert) this panics if a set with a
+    /// maximum number of entries is full.
+    pub fn insert_batch<I: IntoIterator<Item = T>>(&mut self, iter: I) -> Vec<RcIndex<C>> {
+        let iter = iter.into_iter();
+        let (lower, _) = iter.size_hint();
+        self.reserve(lower);
+        let mut indices = Vec::with_capacity(lower);
+        indices.extend(iter.map(|elem| self.get_or_insert_owned(elem)));
+        indices
+    }
+    /// Consumes the set and splits its elements by the predicate.
+    ///
+    /// The first set contains all elements for which `pred` returned `true`,
+    /// the second set contains the rest. Elements are moved, not cloned. Both
+    /// sets are new, i.e. all usage counts are zero and outstanding indices
+    /// into the consumed set become useless. The maximum number of entries
+    /// and the epoch are retained.
+    pub fn partition<F>(self, mut pred: F) -> (Self, Self)
+    where
+        F: FnMut(&T) -> bool,
+        S: Clone,
+    {
+        let mut matching = self.empty_like();
+        let mut rest = self.empty_like();
+        for elem in self {
+            let target = if pred(&elem) {
+                &mut matching
+            } else {
+                &mut rest
+            };
+            // the elements are already distinct
+            let _ = target.insert(elem);
+        }
+        (matching, rest)
+    }
+    /// Moves all elements of `other` into this set, leaving `other` empty.
+    ///
+    /// Elements that are already in this set are dropped, i.e. the entries of
+    /// this set are kept and its indices stay valid. The moved elements are
+    /// unused in this set. `other` retains its capacity but all of its
+    /// outstanding indices become useless.
+    ///
+    /// # Panics
+    ///
+    /// This panics under the same conditions as [`insert()`](#method.insert).
+    pub fn append<D, R>(&mut self, other: &mut IndexedHashSet<T, D, R>)
+    where
+        D: Counter,
+        R: BuildHasher,
+    {
+        // drop the self-references before their targets are moved out. Then,
+        // `other` stays consistent even if an insertion panics.
+        other.map.clear();
+        let mut arena = std::mem::take(&mut other.arena);
+        self.reserve(arena.len());
+        // remove slot by slot, as draining the arena would drop its capacity
+        for slot in 0..arena.capacity() {
+            if let Some((_, a_idx)) = arena.get_unknown_gen(slot) {
+                let entry = arena.remove(a_idx).unwrap();
+                let _ = self.insert(entry.elem.into_inner());
+            }
+        }
+        // the generations of the emptied arena keep old indices invalid
+        other.arena = arena;
+    }
+    /// Consumes the set and returns all elements together with their usage
+    /// counts, including the unused ones.
+    ///
+    /// This is a low-level escape hatch, e.g. for custom persistence. The
+    /// counts are only informative as the indices are useless without the
+    /// set. Use [`from_elements()`](#method.from_elements) to rebuild a set.
+    pub fn into_elements(self) -> Vec<(T, usize)> {
+        let IndexedHashSet { arena, map, .. } = self;
+        // drop the self-references before their targets are moved out
+        drop(map);
+        arena
+            .into_iter()
+            .map(|entry| {
+                let cnt = entry.cnt();
+                (entry.elem.into_inner(), cnt)
+            })
+            .collect()
+    }
+    /// Consumes the set and yields all elements together with their raw arena
+    /// indices, including the unused ones.
+    ///
+    /// This is the consuming counterpart of [`iter_raw()`](#method.iter_raw),
+    /// e.g. to rewrite external raw indices when the elements are moved into
+    /// another structure.
+    pub fn into_iter_with_raw(self) -> impl Iterator<Item = (AIndex, T)> {
+        let IndexedHashSet { mut arena, map, .. } = self;
+        // drop the self-references before their targets are moved out
+        drop(map);
+        (0..arena.capacity()).filter_map(move |slot| {
+            let (_, a_idx) = arena.get_unknown_gen(slot)?;
+            let entry = arena.remove(a_idx)?;
+            Some((a_idx, entry.elem.into_inner()))
+        })
+    }
+    /// Builds a set from elements, e.g. returned by
+    /// [`into_elements()`](#method.into_elements).
+    ///
+    /// Just as for the `FromIterator` implementation all usage counts are
+    /// zero.
+    pub fn from_elements(iter: impl IntoIterator<Item = T>) -> Self
+    where
+        S: Default,
+    {
+        iter.into_iter().collect()
+    }
+    /// A new, empty set with the same configuration.
+    fn empty_like(&self) -> Self
+    where
+        S: Clone,
+    {
+        Self {
+            max_entries: self.max_entries,
+            epoch: self.epoch,
+            ..Self::with_hasher(self.map.hasher().clone())
+        }
+    }
+    /// Drop all entries whose `usage_cnt` is zero.
+    ///
+    /// Entries inserted by [`insert_persistent()`](#method.insert_persistent)
+    /// are kept.
+    ///
+    /// As this requires `&mut self` it can never run while the set is
+    /// borrowed, e.g. during an iteration. Entries are only dropped if no
+    /// `RcIndex` to them exists at the time of calling. This includes indices
+    /// that were just returned by `get_index_by_hash()` or other methods as
+    /// those increment the count on creation.
+    ///
+    /// The arena is only scanned if a usage count dropped to zero since the
+    /// last call. Otherwise, this returns `0` immediately.
+    pub fn drop_unused(&mut self) -> usize {
+        // lower the flag before the scan, so counts dropping to zero during
+        // the scan, e.g. on other threads, raise it again
+        if !C::take(&self.dirty) {
+            return self.record_gc(0);
+        }
+
+        // tell Rust that both mutable borrows are distinct.
+        let arena = &mut self.arena;
+        let map = &mut self.map;
+
+        let before = arena.len();
+
+        arena.retain(|_, entry| {
+            if !entry.is_used() {
+                map.remove(entry.elem().wrap());
+                false
+            } else {
+                true
+            }
+        });
+
+        let collected = before - arena.len();
+        trace_event!(
+            tracing::Level::DEBUG,
+            scanned = before,
+            collected,
+            len = arena.len(),
+            "dropped unused entries"
+        );
+        self.record_gc(collected)
+    }
+    /// Statistics of insertions and collections over the lifetime of the
+    /// set.
+    ///
+    /// Only insertions of new elements and calls of
+    /// [`drop_unused()`](#method.drop_unused), or its parallel counterpart,
+    /// are recorded, regardless if the call scanned the arena.
+    pub fn gc_metrics(&self) -> GcMetrics {
+        self.metrics
+    }
+    /// Records a call of `drop_unused()` and returns `collected`.
+    fn record_gc(&mut self, collected: usize) -> usize {
+        self.metrics.gc_runs += 1;
+        self.metrics.total_collected += collected as u64;
+        self.metrics.last_collected = collected;
+        collected
+    }
+    /// Same as [`drop_unused()`](#method.drop_unused) but reports the number
+    /// of entries and the memory reclaimed.
+    pub fn drop_unused_report(&mut self) -> GcReport {
+        let entries_before = self.len();
+        let removed = self.drop_unused();
+        // the slot of the entry becomes available to new entries, the boxed
+        // element and the allocation of the count with its two reference
+        // counts are freed
+        let per_entry = std::mem::size_of::<Entry<T, C>>()
+            + std::mem::size_of::<T>()
+            + 3 * std::mem::size_of::<usize>();
+        GcReport {
+            removed,
+            entries_before,
+            entries_after: self.len(),
+            approx_bytes_freed: removed * per_entry,
+        }
+    }
+    /// Removes and yields the elements for which `pred` returns `true`, one
+    /// at a time.
+    ///
+    /// In contrast to [`retain_collect()`](#method.retain_collect) the
+    /// removal is lazy. An element is only removed when it is yielded by the
+    /// returned iterator, i.e. if the iterator is dropped early the remaining
+    /// matching elements stay in the set. Elements are removed regardless of
+    /// their usage counts, so outstanding indices to them can no longer be
+    /// used to access the set.
+    pub fn drain_where<F>(&mut self, pred: F) -> DrainWhere<'_, T, C, S, F>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        DrainWhere {
+            set: self,
+            slot: 0,
+            pred,
+        }
+    }
+    /// Keeps only the elements for which `pred` returns `true` and returns an
+    /// index to each of them.
+    ///
+    /// The other entries are removed regardless of their usage counts, i.e.
+    /// outstanding indices to them can no longer be used to access the set.
+    /// The returned indices keep the retained entries alive.
+    pub fn retain_collect<F>(&mut self, mut pred: F) -> Vec<RcIndex<C>>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        // tell Rust that the mutable borrows are distinct.
+        let arena = &mut self.arena;
+        let map = &mut self.map;
+        let dirty = &self.dirty;
+        let epoch = self.epoch;
+
+        let mut kept = Vec::new();
+        arena.retain(|a_idx, entry| {
+            if pred(entry.elem()) {
+                entry.touched.store(epoch, atomic::Ordering::Relaxed);
+                kept.push(RcIndex::new(a_idx, entry.cnt_handle(), dirty.clone()));
+                true
+            } else {
+                map.remove(entry.elem().wrap());
+                false
+            }
+        });
+        kept
+    }
+    /// Keeps only the elements for which `pred` returns `true` and relocates
+    /// them afterwards to eliminate the free slots of the arena.
+    ///
+    /// Returns the mapping from the old to the new raw indices, see
+    /// [`RcIndex::into_raw()`](./struct.RcIndex.html#method.into_raw), to fix
+    /// up external stores of raw indices. Old raw indices never resolve to a
+    /// relocated entry.
+    ///
+    /// As relocation would invalidate `RcIndex`es, nothing is done and `None`
+    /// is returned if any entry is used by an index. Pinned entries are
+    /// relocated as usual.
+    pub fn retain_and_compact<F>(&mut self, mut pred: F) -> Option<CompactionMap>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        if self.arena.iter().any(|(_, e)| e.cnt() != 0) {
+            return None;
+        }
+
+        // tell Rust that both mutable borrows are distinct.
+        let arena = &mut self.arena;
+        let map = &mut self.map;
+        arena.retain(|_, entry| {
+            if pred(entry.elem()) {
+                true
+            } else {
+                map.remove(entry.elem().wrap());
+                false
+            }
+        });
+
+        // draining bumps the generation, so old indices stay invalid. The
+        // elements stay boxed, i.e. the keys of the map remain valid.
+        let entries = arena.drain().collect::<Vec<_>>();
+        arena.reserve(entries.len());
+        let mut moved = Vec::with_capacity(entries.len());
+        for (old, entry) in entries {
+            let new = arena.insert(entry);
+            *map.get_mut(arena[new].elem().wrap()).unwrap() = new;
+            moved.push((old, new));
+        }
+        self.gc_cursor = 0;
+        Some(CompactionMap { moved })
+    }
+    /// The current epoch of the set, see [`set_epoch()`](#method.set_epoch).
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+    /// Set the current epoch of the set.
+    ///
+    /// Entries are stamped with the current epoch whenever they are touched,
+    /// i.e. when they are inserted or a new `RcIndex` to them is created by
+    /// the set. Indices obtained without the set, e.g. by cloning another
+    /// `RcIndex`, don't touch the entry.
+    ///
+    /// The stamps allow to keep recently touched entries on collection, see
+    /// [`drop_unused_older_than()`](#method.drop_unused_older_than). The
+    /// epoch is supplied by the user, e.g. a tick counter or a timestamp.
+    /// Initially, the epoch is zero.
+    pub fn set_epoch(&mut self, epoch: u64) {
+        self.epoch = epoch;
+    }
+    /// Drop all entries whose `usage_cnt` is zero and that were last touched
+    /// before `current_epoch - max_age`.
+    ///
+    /// Unused entries touched more recently are kept. See
+    /// [`set_epoch()`](#method.set_epoch) for when entries are touched.
+    pub fn drop_unused_older_than(&mut self, current_epoch: u64, max_age: u64) -> usize {
+        let threshold = current_epoch.saturating_sub(max_age);
+        // tell Rust that both mutable borrows are distinct.
+        let arena = &mut self.arena;
+        let map = &mut self.map;
+
+        let before = arena.len();
+
+        arena.retain(|_, entry| {
+            if !entry.is_used() && entry.touched.load(atomic::Ordering::Relaxed) < threshold {
+                map.remove(entry.elem().wrap());
+                false
+            } else {
+                true
+            }
+        });
+
+        before - arena.len()
+    }
+    /// Drop the oldest unused entries until at most `max_unused` unused
+    /// entries are left.
+    ///
+    /// The age of an entry is determined by the order of insertion, i.e. the
+    /// unused entries inserted first are dropped first. This allows to keep a
+    /// bounded number of unused entries as cache of the most recently inserted
+    /// elements. Touching an entry, e.g. by a lookup, does not make it recent.
+    /// Returns the number of dropped entries.
+    pub fn truncate_unused_to(&mut self, max_unused: usize) -> usize {
+        let mut unused = self
+            .arena
+            .iter()
+            .filter(|(_, e)| !e.is_used())
+            .map(|(a_idx, e)| (e.seq, a_idx))
+            .collect::<Vec<_>>();
+        if unused.len() <= max_unused {
+            return 0;
+        }
+
+        let excess = unused.len() - max_unused;
+        unused.select_nth_unstable(excess - 1);
+        for (_, a_idx) in &unused[..excess] {
+            let entry = self.arena.remove(*a_idx).unwrap();
+            self.map.remove(entry.elem().wrap());
+        }
+        excess
+    }
+    /// Drop entries whose `usage_cnt` is zero but scan at most `max_scan`
+    /// slots of the arena.
+    ///
+    /// The scan continues where the last call stopped. Consequently, repeated
+    /// calls eventually cover the whole arena which allows to spread the work
+    /// of [`drop_unused()`](#method.drop_unused) over time.
+    ///
+    /// Returns the number of dropped entries and if the end of the arena was
+    /// reached, i.e. a full sweep was completed. In that case the next call
+    /// starts at the beginning of the arena again.
+    pub fn drop_unused_bounded(&mut self, max_scan: usize) -> (usize, bool) {
+        let capacity = self.arena.capacity();
+        if self.gc_cursor >= capacity {
+            self.gc_cursor = 0;
+        }
+
+        let start = self.gc_cursor;
+        let end = start.saturating_add(max_scan).min(capacity);
+        let mut collected = 0;
+
+        for slot in start..end {
+            let a_idx = match self.arena.get_unknown_gen(slot) {
+                Some((entry, a_idx)) if !entry.is_used() => a_idx,
+                _ => continue,
+            };
+            let entry = self.arena.remove(a_idx).unwrap();
+            self.map.remove(entry.elem().wrap());
+            collected += 1;
+        }
+
+        let completed = end == capacity;
+        self.gc_cursor = if completed { 0 } else { end };
+        (collected, completed)
+    }
+    /// Iterates over all used elements in the set, i.e. those with
+    /// `usage_cnt != 0` and the persistent ones.
+    pub fn iter(&self) -> Iter<'_, T, C> {
+        Iter {
+            inner: self.arena.iter(),
+        }
+    }
+    /// Iterates over all used elements in the order of their insertion.
+    ///
+    /// In contrast to [`iter()`](#method.iter) the elements are sorted, which
+    /// requires an allocation. Removing entries, e.g. by
+    /// [`drop_unused()`](#method.drop_unused), does not change the relative
+    /// order of the remaining ones.
+    pub fn iter_ordered(&self) -> impl Iterator<Item = &T> {
+        let mut used = self
+            .arena
+            .iter()
+            .filter(|(_, e)| e.is_used())
+            .map(|(_, e)| (e.seq, e.elem()))
+            .collect::<Vec<_>>();
+        used.sort_unstable_by_key(|(seq, _)| *seq);
+        used.into_iter().map(|(_, elem)| elem)
+    }
+    /// Calls `f` on each used element, i.e. on the elements yielded by
+    /// [`iter()`](#method.iter), in the same order.
+    ///
+    /// The arena is walked once without an intermediate iterator, e.g. for
+    /// streaming the elements into a serializer.
+    pub fn for_each_used<'a, F: FnMut(&'a T)>(&'a self, mut f: F) {
+        for (_, entry) in &self.arena {
+            if entry.is_used() {
+                f(entry.elem());
+            }
+        }
+    }
+    /// Finds the first used element that matches the predicate and returns
+    /// it together with a new index to it.
+    ///
+    /// The elements are searched in the arbitrary order of the arena. Thus,
+    /// if several elements match it is not defined which one is returned.
+    pub fn find<F>(&self, mut pred: F) -> Option<(RcIndex<C>, &T)>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        self.arena
+            .iter()
+            .find(|(_, e)| e.is_used() && pred(e.elem()))
+            .map(|(a_idx, e)| (self.aidx_to_rcidx(a_idx), e.elem()))
+    }
+    /// Counts the used elements that match the predicate.
+    ///
+    /// Just as [`iter()`](#method.iter) only elements with `usage_cnt != 0`
+    /// are considered. No indices are created.
+    pub fn count_where<F>(&self, mut pred: F) -> usize
+    where
+        F: FnMut(&T) -> bool,
+    {
+        self.iter().filter(|e| pred(e)).count()
+    }
+    /// The distribution of the usage counts of all entries.
+    ///
+    /// Maps each occurring usage count, including zero, to the number of
+    /// entries with this count.
+    pub fn count_histogram(&self) -> BTreeMap<usize, usize> {
+        let mut histogram = BTreeMap::new();
+        for (_, entry) in self.arena.iter() {
+            *histogram.entry(entry.cnt()).or_insert(0) += 1;
+        }
+        histogram
+    }
+    /// Get the used elements sorted by the comparator.
+    ///
+    /// The set itself is unordered, i.e. the order of [`iter()`](#method.iter)
+    /// is arbitrary and may change when entries are dropped and their slots
+    /// reused. This is the way to get a deterministic order.
+    pub fn sorted_by<F>(&self, mut cmp: F) -> Vec<&T>
+    where
+        F: FnMut(&T, &T) -> Ordering,
+    {
+        let mut elems = self.iter().collect::<Vec<_>>();
+        elems.sort_by(|a, b| cmp(a, b));
+        elems
+    }
+    /// Copies the used elements into a contiguous buffer.
+    ///
+    /// The order of the elements is as arbitrary as that of
+    /// [`iter()`](#method.iter). Use [`sorted_by()`](#method.sorted_by) first
+    /// if a deterministic order is required.
+    pub fn collect_used(&self) -> Box<[T]>
+    where
+        T: Copy,
+    {
+        self.iter().copied().collect()
+    }
+    /// Iterates over all used elements together with their raw arena index.
+    ///
+    /// In contrast to an `RcIndex` the raw index (an [`ArenaIndex`]) does not
+    /// affect the usage count. Hence,
+    /// the entry may be dropped while a raw index to it is held. Use
+    /// [`promote()`](#method.promote) to get an `RcIndex` from a raw index
+    /// that is still valid.
+    ///
+    /// [`ArenaIndex`]: ./struct.ArenaIndex.html
+    pub fn iter_raw(&self) -> impl Iterator<Item = (AIndex, &T)> {
+        self.arena
+            .iter()
+            .filter(|(_, e)| e.is_used())
+            .map(|(a_idx, e)| (a_idx, e.elem()))
+    }
+    /// Iterates over all used elements together with non-owning indices.
+    ///
+    /// In contrast to `RcIndex`es the yielded [`WeakIndex`]es don't keep the
+    /// entries alive, i.e. the usage counts are unchanged. A `WeakIndex` can
+    /// be upgraded later on if the entry should be kept.
+    ///
+    /// [`WeakIndex`]: ./struct.WeakIndex.html
+    pub fn iter_weak_indexed(&self) -> impl Iterator<Item = (WeakIndex<C>, &T)> {
+        self.arena
+            .iter()
+            .filter(|(_, e)| e.is_used())
+            .map(move |(a_idx, e)| {
+                let weak = WeakIndex {
+                    inner: a_idx,
+                    cnt: C::downgrade(&e.usage_cnt),
+                    dirty: self.dirty.clone(),
+                };
+                (weak, e.elem())
+            })
+    }
+    /// The generation of the entry in the arena slot `raw_slot`, if occupied.
+    ///
+    /// Whenever an entry is dropped and its slot is reused by a new entry the
+    /// generation advances. Comparing the generation with the one of an old
+    /// raw index shows if its slot was reused, see
+    /// [`RcIndex::slot()`](./struct.RcIndex.html#method.slot).
+    pub fn generation_of(&self, raw_slot: usize) -> Option<u64> {
+        let (_, a_idx) = self.arena.get_unknown_gen(raw_slot)?;
+        Some(a_idx.into_raw_parts().1)
+    }
+    /// Get an `RcIndex` from a raw arena index.
+    ///
+    /// Returns `None` if the entry of the raw index was already dropped. Due to
+    /// the generations of the arena a raw index of a dropped entry is never
+    /// promoted to another entry that reuses the slot.
+    pub fn promote(&self, raw: AIndex) -> Option<RcIndex<C>> {
+        if !self.arena.contains(raw) {
+            return None;
+        }
+        Some(self.aidx_to_rcidx(raw))
+    }
+    /// Iterates over all elements in the set, including the unused ones.
+    ///
+    /// Each element is yielded together with a flag if it is used, i.e. if
+    /// its `usage_cnt != 0` or it is persistent.
+    pub fn iter_all(&self) -> impl Iterator<Item = (&T, bool)> {
+        self.arena.iter().map(|(_, e)| (e.elem(), e.is_used()))
+    }
+    /// Iterates over all used elements in the set together with their usage
+    /// counts.
+    ///
+    /// Just as [`iter()`](#method.iter) persistent elements are included,
+    /// possibly with a count of zero.
+    pub fn iter_with_cnt(&self) -> impl Iterator<Item = (&T, usize)> {
+        self.arena
+            .iter()
+            .filter(|(_, e)| e.is_used())
+            .map(|(_, e)| (e.elem(), e.cnt()))
+    }
+    /// Returns the respective `RcIndex` for an index of the arena.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the arena index is not present. However, since these
+    /// kind of indices are only used internally this should never be the case.
+    ///
+    /// The entry is touched in the current epoch.
+    fn aidx_to_rcidx(&self, a_idx: AIndex) -> RcIndex<C> {
+        let entry = &self.arena[a_idx];
+        entry.touched.store(self.epoch, atomic::Ordering::Relaxed);
+        let handle = entry.cnt_handle();
+        // increments the count, so there is no moment where a returned index
+        // points to an unused entry
+        RcIndex::new(a_idx, handle, self.dirty.clone())
+    }
+}
+
+/// Interning of shared elements.
+///
+/// An `Rc<T>` hashes and compares like its `T`. Hence, a set of `Rc<T>`
+/// holds one canonical `Rc` per distinct `T` which is cheap to clone out of
+/// the set. The internal self-references point to the boxed `Rc`s of the
+/// entries, not to the shared `T`s. The same applies to `Arc<T>`, see
+/// [`get_arc_by_hash()`](#method.get_arc_by_hash).
+impl<T, C, S> IndexedHashSet<std::rc::Rc<T>, C, S>
+where
+    T: Eq + Hash,
+    C: Counter,
+    S: BuildHasher,
+{
+    /// Get a clone of the stored `Rc` by hash.
+    ///
+    /// In contrast to [`get_ref_by_hash()`](#method.get_ref_by_hash) the
+    /// lookup is possible with any `Q` the shared `T` can be borrowed as, e.g.
+    /// a `&str` for an `Rc<String>`.
+    pub fn get_rc_by_hash<Q>(&self, key: &Q) -> Option<std::rc::Rc<T>>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        if self.arena.is_empty() {
+            return None;
+        }
+        // `Rc<T>` hashes like `T` and `T` like `Q` as it borrows as `Q`
+        let hash = self.hash_of(key);
+        self.map
+            .raw_entry()
+            .from_hash(hash, |k| T::borrow(k.as_ref()) == key)
+            .map(|(k, _)| k.as_ref().clone())
+    }
+}
+
+/// Interning of shared, thread-safe elements, see the `Rc<T>` counterpart
+/// [`get_rc_by_hash()`](#method.get_rc_by_hash).
+impl<T, C, S> IndexedHashSet<std::sync::Arc<T>, C, S>
+where
+    T: Eq + Hash,
+    C: Counter,
+    S: BuildHasher,
+{
+    /// Get a clone of the stored `Arc` by hash.
+    ///
+    /// In contrast to [`get_ref_by_hash()`](#method.get_ref_by_hash) the
+    /// lookup is possible with any `Q` the shared `T` can be borrowed as, e.g.
+    /// a `&str` for an `Arc<String>`.
+    pub fn get_arc_by_hash<Q>(&self, key: &Q) -> Option<std::sync::Arc<T>>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        if self.arena.is_empty() {
+            return None;
+        }
+        // `Arc<T>` hashes like `T` and `T` like `Q` as it borrows as `Q`
+        let hash = self.hash_of(key);
+        self.map
+            .raw_entry()
+            .from_hash(hash, |k| T::borrow(k.as_ref()) == key)
+            .map(|(k, _)| k.as_ref().clone())
+    }
+}
+
+/// A set of `&'static str`s interns string literals without allocating a
+/// `String` for each. The internal self-references point to the boxed
+/// `&str`s, lookups work with plain `&str`s, e.g. `set.get("foo")`.
+impl<C, S> IndexedHashSet<&'static str, C, S>
+where
+    C: Counter,
+    S: BuildHasher,
+{
+    /// Get the index of a static string, inserting it if not present.
+    ///
+    /// # Panics
+    ///
+    /// Just as [`insert()`](#method.insert) this panics if a set with a
+    /// maximum number of entries is full.
+    pub fn intern_static(&mut self, s: &'static str) -> RcIndex<C> {
+        self.get_or_insert_owned(s)
+    }
+}
+
+impl<T, C, S> Default for IndexedHashSet<T, C, S>
+where
+    C: Counter,
+    S: Default,
+{
+    fn default() -> Self {
+        Self {
+            arena: Default::default(),
+            map: Default::default(),
+            gc_cursor: 0,
+            max_entries: None,
+            epoch: 0,
+            next_seq: 0,
+            dirty: C::new_flag(),
+            metrics: GcMetrics::default(),
+        }
+    }
+}
+
+/// Allows to access the set like `set[&rc_idx]`.
+///
+/// This panics if the `RcIndex` used is not from this `IndexedHashSet`.
+impl<'a, T, C, S> std::ops::Index<&'a RcIndex<C>> for IndexedHashSet<T, C, S>
+where
+    T: Eq + Hash,
+    C: Counter,
+    S: BuildHasher,
+{
+    type Output = T;
+
+    fn index(&self, index: &'a RcIndex<C>) -> &Self::Output {
+        match self.get_ref_by_index_result(index) {
+            Ok(elem) => elem,
+            Err(err) => panic!("{}", err),
+        }
+    }
+}
+
+/// Collects the elements into a new set.
+///
+/// **Attention:** The indices of the inserted elements are dropped. Hence,
+/// all usage counts of a collected set are zero and the whole set is emptied
+/// by the next [`drop_unused()`]. Collect into an [`IndexHolder`] to keep the
+/// indices.
+///
+/// The set reserves capacity for the lower bound of the iterator's size hint
+/// up front.
+///
+/// [`drop_unused()`]: ./struct.IndexedHashSet.html#method.drop_unused
+/// [`IndexHolder`]: ./struct.IndexHolder.html
+impl<T, C, S> std::iter::FromIterator<T> for IndexedHashSet<T, C, S>
+where
+    T: Eq + Hash,
+    C: Counter,
+    S: BuildHasher + Default,
+{
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        // `extend()` reserves for the size hint
+        let mut set = Self::default();
+        set.extend(iter);
+        set
+    }
+}
+
+/// Inserts the elements into the set.
+///
+/// **Attention:** Just as for the `FromIterator` implementation the indices
+/// of the inserted elements are dropped. Hence, new elements are unused and
+/// dropped by the next [`drop_unused()`].
+///
+/// The set reserves capacity for the lower bound of the iterator's size hint
+/// up front.
+///
+/// [`drop_unused()`]: ./struct.IndexedHashSet.html#method.drop_unused
+impl<T, C, S> Extend<T> for IndexedHashSet<T, C, S>
+where
+    T: Eq + Hash,
+    C: Counter,
+    S: BuildHasher,
+{
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for elem in iter {
+            let _ = self.insert(elem);
+        }
+    }
+}
+
+/// A set together with indices to its elements.
+///
+/// Collecting into this type instead of an [`IndexedHashSet`] keeps an index
+/// for each collected element. Thus, the elements stay in use as long as the
+/// indices are held.
+///
+/// ```
+/// # use indexed_hash_set::IndexHolder;
+/// let holder: IndexHolder<_> = vec!["a", "b", "a"].into_iter().collect();
+/// let (mut set, indices) = holder.into_parts();
+/// assert_eq!(set.drop_unused(), 0);
+/// assert_eq!(set.len(), 2);
+/// assert_eq!(indices.len(), 3);
+/// assert_eq!(indices[0].cnt(), 2);
+/// ```
+///
+/// [`IndexedHashSet`]: ./struct.IndexedHashSet.html
+#[derive(Debug)]
+pub struct IndexHolder<T, C = RcCounter>
+where
+    C: Counter,
+{
+    set: IndexedHashSet<T, C>,
+    /// One index per collected element in the order of collection.
+    indices: Vec<RcIndex<C>>,
+}
+
+impl<T, C> IndexHolder<T, C>
+where
+    C: Counter,
+{
+    /// The set of collected elements.
+    pub fn set(&self) -> &IndexedHashSet<T, C> {
+        &self.set
+    }
+    /// The indices in the order of collection.
+    ///
+    /// Equal elements collected several times have one index each, all
+    /// pointing to the same entry.
+    pub fn indices(&self) -> &[RcIndex<C>] {
+        &self.indices
+    }
+    /// Splits into the set and the indices.
+    pub fn into_parts(self) -> (IndexedHashSet<T, C>, Vec<RcIndex<C>>) {
+        (self.set, self.indices)
+    }
+}
+
+impl<T, C> std::iter::FromIterator<T> for IndexHolder<T, C>
+where
+    T: Eq + Hash,
+    C: Counter,
+{
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut set = IndexedHashSet::default();
+        let indices = iter
+            .into_iter()
+            .map(|elem| set.get_or_insert_owned(elem))
+            .collect();
+        Self { set, indices }
+    }
+}
+
+/// Consumes the set and yields **all** elements, including the unused ones.
+///
+/// As the set is consumed outstanding `RcIndex`es can no longer be used to
+/// access the elements. Therefore, the usage counts are irrelevant and all
+/// entries are yielded.
+impl<T, C, S> IntoIterator for IndexedHashSet<T, C, S>
+where
+    C: Counter,
+{
+    type Item = T;
+    type IntoIter = IntoIter<T, C>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let IndexedHashSet { arena, map, .. } = self;
+        // drop the self-references before their targets are moved out
+        drop(map);
+        IntoIter {
+            inner: arena.into_iter(),
+        }
+    }
+}
+
+/// An owning iterator over the elements of an `IndexedHashSet`.
+///
+/// Created by the `IntoIterator` implementation of the set. As all entries
+/// are yielded its [`len()`](#method.len) equals the
+/// [`len()`](./struct.IndexedHashSet.html#method.len) of the consumed set.
+#[derive(Debug)]
+pub struct IntoIter<T, C: Counter = RcCounter> {
+    inner: generational_arena::IntoIter<Entry<T, C>>,
+}
+
+impl<T, C: Counter> Iterator for IntoIter<T, C> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|entry| entry.elem.into_inner())
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<T, C: Counter> ExactSizeIterator for IntoIter<T, C> {}
+
+/// An iterator over the used elements of an `IndexedHashSet`.
+///
+/// Created by [`iter()`](./struct.IndexedHashSet.html#method.iter). The
+/// elements are yielded in the order of the arena which can also be
+/// traversed in reverse.
+#[derive(Debug)]
+pub struct Iter<'a, T, C: Counter = RcCounter> {
+    inner: generational_arena::Iter<'a, Entry<T, C>>,
+}
+
+impl<'a, T, C: Counter> Iterator for Iter<'a, T, C> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.find(|(_, e)| e.is_used()).map(|(_, e)| e.elem())
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.inner.size_hint().1)
+    }
+}
+
+impl<T, C: Counter> DoubleEndedIterator for Iter<'_, T, C> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner
+            .rfind(|(_, e)| e.is_used())
+            .map(|(_, e)| e.elem())
+    }
+}
+
+impl<T, C: Counter> std::iter::FusedIterator for Iter<'_, T, C> {}
+
+/// A lazy iterator removing the elements that match a predicate.
+///
+/// Created by [`drain_where()`](./struct.IndexedHashSet.html#method.drain_where).
+pub struct DrainWhere<'a, T, C: Counter, S, F> {
+    set: &'a mut IndexedHashSet<T, C, S>,
+    /// Arena slot where the next search starts.
+    slot: usize,
+    pred: F,
+}
+
+impl<T, C, S, F> Iterator for DrainWhere<'_, T, C, S, F>
+where
+    T: Eq + Hash,
+    C: Counter,
+    S: BuildHasher,
+    F: FnMut(&T) -> bool,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.slot < self.set.arena.capacity() {
+            let slot = self.slot;
+            self.slot += 1;
+            let a_idx = match self.set.arena.get_unknown_gen(slot) {
+                Some((entry, a_idx)) if (self.pred)(entry.elem()) => a_idx,
+                _ => continue,
+            };
+            let entry = self.set.arena.remove(a_idx).unwrap();
+            self.set.map.remove(entry.elem().wrap());
+            return Some(entry.elem.into_inner());
+        }
+        None
+    }
+}
+
+/// The `!Send` internal references are only used internally. Therefore, this
+/// type is safe to be `Send` as long as the elements and the usage counts are
+/// `Send`, i.e. with the [`ArcCounter`](./enum.ArcCounter.html):
+///
+/// ```
+/// # use indexed_hash_set::{ArcCounter, IndexedHashSet};
+/// fn assert_send<S: Send>(_: S) {}
+///
+/// assert_send(IndexedHashSet::<u32, ArcCounter>::default());
+/// ```
+///
+/// The usage counts of the default [`RcCounter`](./enum.RcCounter.html) are
+/// shared with `!Send` `RcIndex`es. Hence, such a set is not `Send`:
+///
+/// ```compile_fail
+/// # use indexed_hash_set::IndexedHashSet;
+/// fn assert_send<S: Send>(_: S) {}
+///
+/// assert_send(IndexedHashSet::<u32>::new());
+/// ```
+///
+/// A set of `!Send` elements is not `Send` either:
+///
+/// ```compile_fail
+/// # use indexed_hash_set::{ArcCounter, IndexedHashSet};
+/// # use std::rc::Rc;
+/// fn assert_send<S: Send>(_: S) {}
+///
+/// assert_send(IndexedHashSet::<Rc<u32>, ArcCounter>::default());
+/// ```
+unsafe impl<T, C, S> Send for IndexedHashSet<T, C, S>
+where
+    T: Send,
+    C: Counter,
+    C::Handle: Send,
+    C::Flag: Send,
+    S: Send,
+{
+}
+
+/// Error of inserting into a full set.
+///
+/// Returned by [`try_insert()`](./struct.IndexedHashSet.html#method.try_insert)
+/// if the set is full even after dropping its unused entries. Contains the
+/// element that could not be inserted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapacityError<T> {
+    elem: T,
+}
+
+impl<T> CapacityError<T> {
+    /// Get back the element that could not be inserted.
+    pub fn into_inner(self) -> T {
+        self.elem
+    }
+}
+
+impl<T> std::fmt::Display for CapacityError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "the set reached its maximum number of entries")
+    }
+}
+
+impl<T: std::fmt::Debug> std::error::Error for CapacityError<T> {}
+
+/// Statistics of collecting the unused entries of a set.
+///
+/// Returned by [`drop_unused_report()`](./struct.IndexedHashSet.html#method.drop_unused_report).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GcReport {
+    /// Number of dropped entries.
+    pub removed: usize,
+    /// Number of entries before the collection.
+    pub entries_before: usize,
+    /// Number of entries after the collection.
+    pub entries_after: usize,
+    /// Estimate of the freed memory in bytes, including the memory of the
+    /// entries' slots that is available to new entries.
+    pub approx_bytes_freed: usize,
+}
+
+/// Statistics of a set over its lifetime.
+///
+/// Returned by [`gc_metrics()`](./struct.IndexedHashSet.html#method.gc_metrics).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct GcMetrics {
+    /// Number of inserted new elements.
+    pub total_inserts: u64,
+    /// Number of entries dropped by all collections.
+    pub total_collected: u64,
+    /// Number of collections.
+    pub gc_runs: u64,
+    /// Number of entries dropped by the last collection.
+    pub last_collected: usize,
+}
+
+/// Result of [`classify_insert()`](./struct.IndexedHashSet.html#method.classify_insert).
+#[derive(Debug, Clone)]
+pub enum Inserted<C: Counter = RcCounter> {
+    /// An equal element was already in the set.
+    Existing(RcIndex<C>),
+    /// The element was newly inserted.
+    New(RcIndex<C>),
+}
+
+impl<C: Counter> Inserted<C> {
+    /// The index of the entry, regardless if it is new or not.
+    pub fn into_index(self) -> RcIndex<C> {
+        match self {
+            Inserted::Existing(idx) | Inserted::New(idx) => idx,
+        }
+    }
+}
+
+/// Reasons why an `RcIndex` can not be resolved by a set.
+///
+/// Returned by [`get_ref_by_index_result()`](./struct.IndexedHashSet.html#method.get_ref_by_index_result).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexError {
+    /// The index is from another set.
+    ///
+    /// This is only detected if the slot of the index does not exist in this
+    /// set or if the index matches an entry of this set that has another
+    /// usage count. Otherwise, a foreign index appears as `Stale` or
+    /// `Collected`.
+    Foreign,
+    /// The slot of the index is occupied by another entry.
+    Stale,
+    /// The entry of the index was removed and its slot is empty.
+    Collected,
+}
+
+impl std::fmt::Display for IndexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            IndexError::Foreign => write!(f, "the index is from another set"),
+            IndexError::Stale => write!(f, "the entry of the index was replaced"),
+            IndexError::Collected => write!(f, "the entry of the index was removed"),
+        }
+    }
+}
+
+impl std::error::Error for IndexError {}
+
+/// A reference-counted index to an entry of the set.
+///
+/// The kind of the count is determined by `C`, see
+/// [`Counter`](./trait.Counter.html).
+#[derive(Debug)]
+pub struct RcIndex<C: Counter = RcCounter> {
+    /// Original index into the arena.
+    inner: AIndex,
+    /// Usage count. Incremented at index construction and decremented at drop.
+    cnt: C::Handle,
+    /// Flag of the set, raised when the usage count drops to zero.
+    dirty: C::Flag,
+}
+
+impl<C: Counter> RcIndex<C> {
+    /// Creates a new reference-counted index.
+    ///
+    /// On creation the `usage_cnt` is incremented.
+    fn new(idx: AIndex, cnt_handle: C::Handle, dirty: C::Flag) -> Self {
+        C::inc(&cnt_handle);
+        trace_event!(
+            tracing::Level::TRACE,
+            cnt = C::get(&cnt_handle),
+            "new index"
+        );
+        Self {
+            inner: idx,
+            cnt: cnt_handle,
+            dirty,
+        }
+    }
+    /// Get the usage count of the element.
+    pub fn cnt(&self) -> usize {
+        C::get(&self.cnt)
+    }
+    /// Get a non-owning index to the same entry.
+    ///
+    /// The returned index does not increment the usage count. Hence, it does
+    /// not keep the entry from being dropped.
+    pub fn downgrade(&self) -> WeakIndex<C> {
+        WeakIndex {
+            inner: self.inner,
+            cnt: C::downgrade(&self.cnt),
+            dirty: self.dirty.clone(),
+        }
+    }
+    /// The slot in the arena of the set the index points to.
+    ///
+    /// This is for debugging only, e.g. together with
+    /// [`generation_of()`](./struct.IndexedHashSet.html#method.generation_of).
+    pub fn slot(&self) -> usize {
+        self.inner.into_raw_parts().0
+    }
+    /// Consumes the index and returns the raw arena index, see
+    /// [`iter_raw()`](./struct.IndexedHashSet.html#method.iter_raw).
+    ///
+    /// The usage count is decremented as the raw index does not count as
+    /// usage.
+    pub fn into_raw(self) -> AIndex {
+        self.inner
+    }
+}
+
+impl<C: Counter> Clone for RcIndex<C> {
+    fn clone(&self) -> Self {
+        C::inc(&self.cnt);
+        Self {
+            inner: self.inner,
+            cnt: self.cnt.clone(),
+            dirty: self.dirty.clone(),
+        }
+    }
+}
+
+/// Indices are equal if they point to the same entry.
+///
+/// Only the position in the arena is compared, not the set. So indices of
+/// different sets may be equal.
+impl<C: Counter> PartialEq for RcIndex<C> {
+    fn eq(&self, other: &Self) -> bool {
+        self.inner == other.inner
+    }
+}
+
+impl<C: Counter> Eq for RcIndex<C> {}
+
+impl<C: Counter> Hash for RcIndex<C> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.inner.into_raw_parts().hash(state);
+    }
+}
+
+/// Indices are ordered by the slot in the arena and then by the generation.
+///
+/// The order is independent of the elements and usage counts, e.g. for
+/// keys of a `BTreeMap`.
+impl<C: Counter> PartialOrd for RcIndex<C> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<C: Counter> Ord for RcIndex<C> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.inner
+            .into_raw_parts()
+            .cmp(&other.inner.into_raw_parts())
+    }
+}
+
+impl<C: Counter> Drop for RcIndex<C> {
+    fn drop(&mut self) {
+        if C::dec(&self.cnt) {
+            C::raise(&self.dirty);
+        }
+        trace_event!(
+            tracing::Level::TRACE,
+            cnt = C::get(&self.cnt),
+            "dropped index"
+        );
+    }
+}
+
+/// A non-owning index to an entry of the set.
+///
+/// Created by [`RcIndex::downgrade()`](./struct.RcIndex.html#method.downgrade).
+/// In contrast to an `RcIndex` it does not count as usage of the entry.
+#[derive(Debug, Clone)]
+pub struct WeakIndex<C: Counter = RcCounter> {
+    /// Original index into the arena.
+    inner: AIndex,
+    /// Usage count that is not kept alive.
+    cnt: C::Weak,
+    /// Flag of the set, see `RcIndex`.
+    dirty: C::Flag,
+}
+
+impl<C: Counter> WeakIndex<C> {
+    /// Get back an `RcIndex`, incrementing the usage count again.
+    ///
+    /// This fails if the entry was already dropped by the set. No access to
+    /// the set is required for this.
+    ///
+    /// _Note:_ With the [`ArcCounter`](./enum.ArcCounter.html) an upgrade on
+    /// another thread that races with `drop_unused()` may still return an
+    /// index to an entry that is about to be dropped.
+    pub fn upgrade(&self) -> Option<RcIndex<C>> {
+        let handle = C::upgrade(&self.cnt)?;
+        Some(RcIndex::new(self.inner, handle, self.dirty.clone()))
+    }
+}
+
+/// An index to an entry of the set that is not yet counted as usage.
+///
+/// Created by [`lazy_index_by_hash()`](./struct.IndexedHashSet.html#method.lazy_index_by_hash).
+/// It is cheap to pass around and only counts as usage once it is pinned.
+#[derive(Debug, Clone)]
+pub struct LazyIndex<C: Counter = RcCounter> {
+    /// Original index into the arena.
+    inner: AIndex,
+    /// Usage count that is not kept alive.
+    cnt: C::Weak,
+}
+
+impl<C: Counter> LazyIndex<C> {
+    /// Get an `RcIndex` from the set, incrementing the usage count.
+    ///
+    /// This fails if the entry was already dropped or if `set` is not the set
+    /// the index was created by.
+    pub fn pin<T, S>(&self, set: &IndexedHashSet<T, C, S>) -> Option<RcIndex<C>>
+    where
+        T: Eq + Hash,
+        S: BuildHasher,
+    {
+        let handle = C::upgrade(&self.cnt)?;
+        let entry = set.arena.get(self.inner)?;
+        if !C::ptr_eq(&entry.usage_cnt, &handle) {
+            return None;
+        }
+        Some(set.aidx_to_rcidx(self.inner))
+    }
+}
+
+/// State of an entry, returned by
+/// [`index_state()`](./struct.IndexedHashSet.html#method.index_state).
+#[derive(Debug)]
+pub enum IndexState<C: Counter = RcCounter> {
+    /// The element is not in the set.
+    Absent,
+    /// The element is in the set but unused, i.e. it is dropped on the next
+    /// [`drop_unused()`](./struct.IndexedHashSet.html#method.drop_unused)
+    /// unless the index is kept.
+    Dead(RcIndex<C>),
+    /// The element is in the set and used.
+    Live(RcIndex<C>),
+}
+
+/// Mapping from old to new raw indices, returned by
+/// [`retain_and_compact()`](./struct.IndexedHashSet.html#method.retain_and_compact).
+#[derive(Debug, Clone)]
+pub struct CompactionMap {
+    /// Pairs of old and new raw indices, sorted by the old slot.
+    moved: Vec<(AIndex, AIndex)>,
+}
+
+impl CompactionMap {
+    /// Get the new raw index of an entry by its old one.
+    ///
+    /// Returns `None` if the entry was removed or `old` was already invalid
+    /// before the compaction.
+    pub fn get(&self, old: AIndex) -> Option<AIndex> {
+        let (slot, _) = old.into_raw_parts();
+        let pos = self
+            .moved
+            .binary_search_by_key(&slot, |(o, _)| o.into_raw_parts().0)
+            .ok()?;
+        let (o, new) = self.moved[pos];
+        if o == old {
+            Some(new)
+        } else {
+            None
+        }
+    }
+    /// Number of relocated entries.
+    pub fn len(&self) -> usize {
+        self.moved.len()
+    }
+    /// Returns `true` if no entry was relocated.
+    pub fn is_empty(&self) -> bool {
+        self.moved.is_empty()
+    }
+    /// Iterates over the pairs of old and new raw indices.
+    pub fn iter(&self) -> impl Iterator<Item = (AIndex, AIndex)> + '_ {
+        self.moved.iter().copied()
+    }
+}
+
+/// Observes the usage count of an entry without counting as usage.
+///
+/// Created by [`count_observer()`](./struct.IndexedHashSet.html#method.count_observer).
+/// This allows, e.g. eviction logic, to poll if an entry is still used
+/// without keeping it alive.
+#[derive(Debug, Clone)]
+pub struct CountObserver<C: Counter = RcCounter> {
+    /// Usage count that is not kept alive.
+    cnt: C::Weak,
+}
+
+impl<C: Counter> CountObserver<C> {
+    /// Get the current usage count of the entry.
+    ///
+    /// Returns `None` if the entry was already dropped by the set.
+    pub fn count(&self) -> Option<usize> {
+        C::upgrade(&self.cnt).map(|handle| C::get(&handle))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    thread_local! {
+        static HASH_CALLS: std::cell::Cell<usize> = Default::default();
+    }
+
+    thread_local! {
+        /// Makes hashing a `PanicHash` of this value panic.
+        static PANIC_ON: Cell<Option<u32>> = Default::default();
+    }
+
+    /// Panics on hashing if requested via `PANIC_ON`.
+    #[derive(Debug, PartialEq, Eq)]
+    struct PanicHash(u32);
+
+    impl Hash for PanicHash {
+        fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+            if PANIC_ON.with(Cell::get) == Some(self.0) {
+                panic!("hashing {}", self.0);
+            }
+            self.0.hash(state)
+        }
+    }
+
+    /// Counts the calls of its `Hash` implementation.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct CountHash(u32);
+
+    impl Hash for CountHash {
+        fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+            HASH_CALLS.with(|c| c.set(c.get() + 1));
+            self.0.hash(state)
+        }
+    }
+
+    fn hash_calls() -> usize {
+        HASH_CALLS.with(|c| c.replace(0))
+    }
+
+    /// FNV-1a with a custom offset basis.
+    #[derive(Debug, Clone, PartialEq)]
+    struct Seeded(u64);
+
+    struct Fnv(u64);
+
+    impl std::hash::Hasher for Fnv {
+        fn finish(&self) -> u64 {
+            self.0
+        }
+        fn write(&mut self, bytes: &[u8]) {
+            for b in bytes {
+                self.0 = (self.0 ^ u64::from(*b)).wrapping_mul(0x100_0000_01b3);
+            }
+        }
+    }
+
+    impl BuildHasher for Seeded {
+        type Hasher = Fnv;
+
+        fn build_hasher(&self) -> Fnv {
+            Fnv(self.0)
+        }
+    }
+
+    /// Set with three entries with each usage count equal to zero.
+    fn standard_set() -> IndexedHashSet<String> {
+        let mut set = IndexedHashSet::new();
+        set.insert("Olaf".to_owned()).unwrap();
+        set.insert("Eijnar".to_owned()).unwrap();
+        set.insert("Harald".to_owned()).unwrap();
+        set
+    }
+
+    #[test]
+    fn unused_entries() {
+        let mut set = standard_set();
+        assert_eq!(set.len(), 3);
+        assert_eq!(set.drop_unused(), 3);
+        assert_eq!(set.len(), 0);
+    }
+
+    #[test]
+    fn bounded_drop_covers_all() {
+        let mut set = IndexedHashSet::new();
+        for i in 0..10 {
+            set.insert(i).unwrap();
+        }
+        let keep = set.get_index_by_hash(&3).unwrap();
+
+        let mut collected = 0;
+        loop {
+            let (cnt, completed) = set.drop_unused_bounded(3);
+            assert!(cnt <= 3);
+            collected += cnt;
+            if completed {
+                break;
+            }
+        }
+        assert_eq!(collected, 9);
+        assert_eq!(set.len(), 1);
+        assert_eq!(set[&keep], 3);
+    }
+
+    #[test]
+    fn bounded_drop_wraps() {
+        let mut set = IndexedHashSet::new();
+        let o1 = set.insert("Olaf".to_owned()).unwrap();
+        let capacity = set.arena.capacity();
+        assert_eq!(set.drop_unused_bounded(capacity - 1), (0, false));
+        assert_eq!(set.drop_unused_bounded(capacity), (0, true));
+
+        // the cursor starts at the beginning again and finds the entry
+        drop(o1);
+        assert_eq!(set.drop_unused_bounded(1), (1, false));
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn usage_cnt() {
+        let mut set = IndexedHashSet::new();
+        let o1 = set.insert("Olaf".to_owned()).unwrap();
+        assert_eq!(o1.cnt(), 1);
+        let _o2 = set.get_index_by_hash("Olaf").unwrap();
+        assert_eq!(o1.cnt(), 2);
+        {
+            let _o3 = o1.clone();
+            assert_eq!(o1.cnt(), 3);
+        }
+        assert_eq!(o1.cnt(), 2);
+    }
+
+    #[test]
+    fn get_or_insert_hashes_once() {
+        let mut set = IndexedHashSet::new();
+        hash_calls();
+        let idx = set.get_or_insert(&CountHash(1));
+        assert_eq!(hash_calls(), 1, "miss path");
+        let again = set.get_or_insert(&CountHash(1));
+        assert_eq!(hash_calls(), 1, "hit path");
+        assert_eq!(idx.cnt(), 2);
+        assert_eq!(set[&again], CountHash(1));
+    }
+
+    #[test]
+    fn into_iter_yields_all() {
+        let set = standard_set();
+        let _o = set.get_index_by_hash("Olaf").unwrap();
+        let len = set.len();
+
+        let mut iter = set.into_iter();
+        assert_eq!(iter.len(), len);
+        assert_eq!(iter.size_hint(), (len, Some(len)));
+        iter.next().unwrap();
+        assert_eq!(iter.len(), len - 1);
+
+        let mut elems = standard_set().into_iter().collect::<Vec<_>>();
+        elems.sort();
+        assert_eq!(elems, ["Eijnar", "Harald", "Olaf"]);
+    }
+
+    #[test]
+    fn insert_into_full_set_collects() {
+        let mut set = IndexedHashSet::with_max_entries(2);
+        let o = set.try_insert("Olaf".to_owned()).unwrap().unwrap();
+        set.try_insert("Eijnar".to_owned()).unwrap().unwrap();
+        assert_eq!(set.len(), 2);
+
+        // "Eijnar" is unused and makes room
+        let h = set.try_insert("Harald".to_owned()).unwrap().unwrap();
+        assert_eq!(set.len(), 2);
+        assert!(set.get_ref_by_hash("Eijnar").is_none());
+        assert_eq!(set[&o], "Olaf");
+        assert_eq!(set[&h], "Harald");
+        // duplicates neither collect nor fail
+        assert!(set.try_insert("Olaf".to_owned()).unwrap().is_none());
+    }
+
+    #[test]
+    fn insert_into_full_set_fails() {
+        let mut set = IndexedHashSet::with_max_entries(1);
+        let _o = set.try_insert("Olaf".to_owned()).unwrap().unwrap();
+        let err = set.try_insert("Eijnar".to_owned()).unwrap_err();
+        assert_eq!(err.into_inner(), "Eijnar");
+        assert_eq!(set.len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "maximum number of entries")]
+    fn insert_into_full_set_panics() {
+        let mut set = IndexedHashSet::with_max_entries(1);
+        let _o = set.insert("Olaf".to_owned()).unwrap();
+        let _ = set.insert("Eijnar".to_owned());
+    }
+
+    #[test]
+    fn iter_all_flags_usage() {
+        let set = standard_set();
+        let _o = set.get_index_by_hash("Olaf").unwrap();
+        let _h = set.get_index_by_hash("Harald").unwrap();
+
+        let mut all = set.iter_all().collect::<Vec<_>>();
+        all.sort();
+        assert_eq!(
+            all,
+            [
+                (&"Eijnar".to_owned(), false),
+                (&"Harald".to_owned(), true),
+                (&"Olaf".to_owned(), true)
+            ]
+        );
+    }
+
+    #[test]
+    fn interleave_indices_and_drop() {
+        let mut set = standard_set();
+        let indices = set
+            .iter_all()
+            .map(|(e, _)| e.clone())
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|e| set.get_index_by_hash(&e).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(set.drop_unused(), 0);
+
+        let mut new_indices = Vec::new();
+        for idx in indices {
+            let elem = set[&idx].clone();
+            // replace the only index by a new one
+            let new_idx = set.get_index_by_hash(&elem).unwrap();
+            drop(idx);
+            assert_eq!(set.drop_unused(), 0);
+            assert_eq!(set[&new_idx], elem);
+            new_indices.push(new_idx);
+        }
+        drop(new_indices);
+        assert_eq!(set.drop_unused(), 3);
+    }
+
+    #[test]
+    fn batch_indices() {
+        let mut set = standard_set();
+        let indices = set.get_indices(&["Harald", "Sven", "Olaf"]);
+        assert_eq!(indices.len(), 3);
+        assert_eq!(set[indices[0].as_ref().unwrap()], "Harald");
+        assert!(indices[1].is_none());
+        assert_eq!(set[indices[2].as_ref().unwrap()], "Olaf");
+        assert_eq!(set.get_cnt("Harald"), Some(1));
+        assert_eq!(set.get_cnt("Olaf"), Some(1));
+        assert_eq!(set.get_cnt("Eijnar"), Some(0));
+        assert_eq!(set.drop_unused(), 1);
+    }
+
+    fn drop_unused_semantics<C: Counter>() {
+        let mut set = IndexedHashSet::<u32, C>::default();
+        let one = set.insert(1).unwrap();
+        let _two = set.insert(2).unwrap();
+        set.insert(3).unwrap();
+        assert_eq!(set.drop_unused(), 1);
+        assert_eq!(set.get_cnt(&1), Some(1));
+
+        let one_clone = one.clone();
+        assert_eq!(one.cnt(), 2);
+        drop(one);
+        assert_eq!(set.drop_unused(), 0);
+        drop(one_clone);
+        assert_eq!(set.drop_unused(), 1);
+        assert_eq!(set.len(), 1);
+        assert_eq!(set.get_cnt(&2), Some(1));
+    }
+
+    #[test]
+    fn rc_counter() {
+        drop_unused_semantics::<RcCounter>();
+    }
+
+    #[test]
+    fn arc_counter() {
+        drop_unused_semantics::<ArcCounter>();
+
+        let mut set = IndexedHashSet::<String, ArcCounter>::default();
+        let idx = set.insert("Olaf".to_owned()).unwrap();
+        let clone = idx.clone();
+        std::thread::spawn(move || drop(clone)).join().unwrap();
+        assert_eq!(idx.cnt(), 1);
+        drop(idx);
+        assert_eq!(set.drop_unused(), 1);
+    }
+
+    #[test]
+    fn find_by_predicate() {
+        let mut set = standard_set();
+        let _o = set.get_index_by_hash("Olaf").unwrap();
+        let _h = set.get_index_by_hash("Harald").unwrap();
+
+        // "Eijnar" is unused and, therefore, not found
+        assert!(set.find(|e| e.starts_with('E')).is_none());
+        let (idx, elem) = set.find(|e| e.ends_with('d')).unwrap();
+        assert_eq!(elem, "Harald");
+        drop(_h);
+        assert_eq!(set.drop_unused(), 1);
+        assert_eq!(set[&idx], "Harald");
+    }
+
+    #[test]
+    fn count_by_predicate() {
+        let mut set = standard_set();
+        let _indices = set.get_indices(&["Olaf", "Eijnar", "Harald"]);
+        let sven = set.insert("Sven".to_owned());
+        assert_eq!(set.count_where(|e| e.len() > 4), 2);
+        drop(sven);
+        assert_eq!(set.count_where(|e| e.len() > 3), 3);
+        assert_eq!(set.get_cnt("Olaf"), Some(1));
+    }
+
+    #[test]
+    fn collect_drops_indices() {
+        let mut set: IndexedHashSet<_> = vec!["Olaf", "Eijnar", "Olaf"].into_iter().collect();
+        assert_eq!(set.len(), 2);
+        assert_eq!(set.get_cnt("Olaf"), Some(0));
+
+        set.extend(vec!["Harald"]);
+        assert_eq!(set.get_cnt("Harald"), Some(0));
+        assert_eq!(set.drop_unused(), 3);
+    }
+
+    #[test]
+    fn collect_with_indices() {
+        let holder: IndexHolder<_> = vec!["Olaf", "Eijnar", "Olaf"].into_iter().collect();
+        assert_eq!(holder.set().get_cnt("Olaf"), Some(2));
+        assert_eq!(holder.set().get_cnt("Eijnar"), Some(1));
+        assert_eq!(holder.set()[&holder.indices()[2]], "Olaf");
+
+        let (mut set, indices) = holder.into_parts();
+        assert_eq!(set.drop_unused(), 0);
+        drop(indices);
+        assert_eq!(set.drop_unused(), 2);
+    }
+
+    #[test]
+    fn multi_membership() {
+        let set = standard_set();
+        assert!(set.contains("Olaf"));
+        assert!(set.contains_all(["Olaf", "Harald"].iter().copied()));
+        assert!(!set.contains_all(vec!["Olaf", "Sven"]));
+        assert!(set.contains_all(Vec::<&str>::new()));
+
+        let mut other = IndexedHashSet::new();
+        let _s = other.insert("Sven".to_owned()).unwrap();
+        assert!(set.is_disjoint(&other));
+        assert!(other.is_disjoint(&set));
+        let _o = other.insert("Olaf".to_owned()).unwrap();
+        assert!(!set.is_disjoint(&other));
+        assert!(!other.is_disjoint(&set));
+    }
+
+    #[test]
+    fn rawindices() {
+        let mut set = standard_set();
+        let indices = set.get_indices(&["Olaf", "Harald"]);
+
+        let raw = set
+            .iter_raw()
+            .map(|(raw, e)| (raw, e.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(raw.len(), 2);
+        assert_eq!(set.get_cnt("Olaf"), Some(1));
+
+        for (raw, elem) in &raw {
+            let idx = set.promote(*raw).unwrap();
+            assert_eq!(set.get_ref_by_index(&idx), Some(elem));
+        }
+
+        drop(indices);
+        set.drop_unused();
+        assert!(set.promote(raw[0].0).is_none());
+    }
+
+    #[test]
+    fn access_by_raw_index() {
+        let mut set = standard_set();
+        let olaf = set.get_index_by_hash("Olaf").unwrap();
+        let _olaf = olaf.clone();
+        let raw = olaf.into_raw();
+        assert_eq!(set.get_cnt("Olaf"), Some(1));
+        assert_eq!(set.get_ref_by_raw(raw).unwrap(), "Olaf");
+
+        let harald = set.get_index_by_hash("Harald").unwrap().into_raw();
+        set.drop_unused();
+        assert!(set.get_ref_by_raw(harald).is_none());
+    }
+
+    #[test]
+    fn merge_keeps_indices() {
+        let mut set = IndexedHashSet::new();
+        let o = set.insert("Olaf".to_owned()).unwrap();
+        let s = set.insert("Sven".to_owned()).unwrap();
+
+        let other = standard_set();
+        let _other_o = other.get_index_by_hash("Olaf").unwrap();
+        set.merge(other);
+
+        assert_eq!(set.len(), 4);
+        assert_eq!(set[&o], "Olaf");
+        assert_eq!(set[&s], "Sven");
+        assert_eq!(o.cnt(), 1);
+        assert_eq!(set.get_cnt("Harald"), Some(0));
+        assert_eq!(set.drop_unused(), 2);
+    }
+
+    #[test]
+    fn weak_indices() {
+        let mut set = standard_set();
+        let o = set.get_index_by_hash("Olaf").unwrap();
+        let weak = o.downgrade();
+        assert_eq!(o.cnt(), 1);
+
+        let upgraded = weak.upgrade().unwrap();
+        assert_eq!(o.cnt(), 2);
+        assert_eq!(set[&upgraded], "Olaf");
+        drop(upgraded);
+        drop(o);
+
+        // unused but not dropped yet
+        let o = weak.upgrade().unwrap();
+        assert_eq!(set.drop_unused(), 2);
+        drop(o);
+        assert_eq!(set.drop_unused(), 1);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn insert_with_precomputed_hash() {
+        let mut set = IndexedHashSet::new();
+        let hash = set.hash_of(&CountHash(7));
+        hash_calls();
+
+        let idx = set.insert_prehashed(hash, CountHash(7)).unwrap();
+        assert_eq!(hash_calls(), 0);
+        assert!(set.insert_prehashed(hash, CountHash(7)).is_none());
+        assert_eq!(set.get_ref_by_hash(&CountHash(7)), Some(&CountHash(7)));
+        assert!(set.insert(CountHash(7)).is_none());
+        assert_eq!(
+            set.get_index_by_hash(&CountHash(7)).unwrap().inner,
+            idx.inner
+        );
+    }
+
+    #[test]
+    fn cloned_by_index() {
+        let mut set = standard_set();
+        let o = set.get_index_by_hash("Olaf").unwrap();
+        let olaf = set.get_cloned_by_index(&o).unwrap();
+
+        assert_eq!(set.drop_unused(), 2);
+        let _s = set.insert("Sven".to_owned()).unwrap();
+        assert_eq!(olaf, "Olaf");
+        assert_eq!(set[&o], olaf);
+    }
+
+    #[test]
+    fn drop_by_age() {
+        let mut set = IndexedHashSet::new();
+        set.insert("Olaf".to_owned()).unwrap();
+        set.set_epoch(5);
+        set.insert("Eijnar".to_owned()).unwrap();
+        set.set_epoch(10);
+        set.insert("Harald".to_owned()).unwrap();
+        let o = set.get_index_by_hash("Olaf").unwrap();
+        set.set_epoch(12);
+        set.insert("Sven".to_owned()).unwrap();
+        drop(o);
+
+        // "Harald" and "Olaf" were touched at 10
+        assert_eq!(set.drop_unused_older_than(15, 5), 1);
+        assert!(!set.contains("Eijnar"));
+        assert_eq!(set.drop_unused_older_than(16, 5), 2);
+        assert!(set.contains("Sven"));
+        assert_eq!(set.drop_unused_older_than(16, 0), 1);
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn index_errors() {
+        let mut set = IndexedHashSet::new();
+        let o = set.insert("Olaf".to_owned()).unwrap();
+        assert_eq!(set.get_ref_by_index_result(&o).unwrap(), "Olaf");
+
+        // same slot and generation but from another set
+        let mut other = IndexedHashSet::new();
+        let other_o = other.insert("Olaf".to_owned()).unwrap();
+        assert_eq!(other_o.inner, o.inner);
+        assert_eq!(
+            set.get_ref_by_index_result(&other_o),
+            Err(IndexError::Foreign)
+        );
+        // slot is out of range
+        for i in 0..10 {
+            other.insert(i.to_string()).unwrap();
+        }
+        let other_9 = other.get_index_by_hash("9").unwrap();
+        assert_eq!(
+            set.get_ref_by_index_result(&other_9),
+            Err(IndexError::Foreign)
+        );
+
+        // indices to removed entries can not be obtained via the public API
+        let raw = o.inner;
+        drop(o);
+        set.drop_unused();
+        let removed = RcIndex::<RcCounter>::new(raw, Default::default(), Default::default());
+        assert_eq!(
+            set.get_ref_by_index_result(&removed),
+            Err(IndexError::Collected)
+        );
+        let _h = set.insert("Harald".to_owned()).unwrap();
+        assert_eq!(
+            set.get_ref_by_index_result(&removed),
+            Err(IndexError::Stale)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "the index is from another set")]
+    fn index_foreign_panics() {
+        let set = standard_set();
+        let mut other = IndexedHashSet::new();
+        let o = other.insert("Olaf".to_owned()).unwrap();
+        let _ = &set[&o];
+    }
+
+    #[test]
+    fn intern_canonical() {
+        let mut set = IndexedHashSet::new();
+        let (o1, r1) = set.intern("Olaf".to_owned());
+        let r1 = r1 as *const String;
+        let (o2, r2) = set.intern(String::from("Olaf"));
+        assert!(std::ptr::eq(r1, r2));
+        assert_eq!(o1.cnt(), 2);
+        assert_eq!(o2.inner, o1.inner);
+        assert_eq!(set.len(), 1);
+    }
+
+    #[test]
+    fn partition_by_len() {
+        let mut set = standard_set();
+        set.extend(vec!["Sven".to_owned(), "Ragnar".to_owned()]);
+        let (long, short) = set.partition(|e| e.len() > 4);
+
+        assert_eq!(long.len(), 3);
+        assert_eq!(short.len(), 2);
+        assert!(long.contains_all(vec!["Eijnar", "Harald", "Ragnar"]));
+        assert!(short.contains_all(vec!["Olaf", "Sven"]));
+        assert!(long.is_disjoint(&short));
+        assert_eq!(long.get_cnt("Ragnar"), Some(0));
+    }
+
+    #[test]
+    fn classify_insertions() {
+        let mut set = standard_set();
+        let sven = match set.classify_insert("Sven".to_owned()) {
+            Inserted::New(idx) => idx,
+            Inserted::Existing(_) => panic!("Sven is new"),
+        };
+        match set.classify_insert("Olaf".to_owned()) {
+            Inserted::Existing(idx) => assert_eq!(set[&idx], "Olaf"),
+            Inserted::New(_) => panic!("Olaf exists"),
+        }
+        let again = set.classify_insert("Sven".to_owned()).into_index();
+        assert_eq!(sven.cnt(), 2);
+        assert_eq!(set[&again], "Sven");
+        assert_eq!(set.len(), 4);
+    }
+
+    #[test]
+    fn sorted_elements() {
+        let mut set = standard_set();
+        let _indices = set.get_indices(&["Olaf", "Eijnar", "Harald"]);
+        let _s = set.insert("Sven".to_owned());
+
+        let sorted = set.sorted_by(|a, b| a.cmp(b));
+        assert_eq!(sorted, ["Eijnar", "Harald", "Olaf", "Sven"]);
+        let by_len = set.sorted_by(|a, b| a.len().cmp(&b.len()).then(b.cmp(a)));
+        assert_eq!(by_len, ["Sven", "Olaf", "Harald", "Eijnar"]);
+    }
+
+    #[test]
+    fn drop_unused_skips_clean_set() {
+        let mut set = IndexedHashSet::new();
+        let olaf = set.insert("Olaf").unwrap();
+        let _harald = set.insert("Harald").unwrap();
+        // the count doesn't reach zero
+        drop(olaf.clone());
+        assert!(!set.dirty.get());
+        assert_eq!(set.drop_unused(), 0);
+
+        drop(olaf);
+        assert!(set.dirty.get());
+        // too young to be dropped, the flag stays raised
+        assert_eq!(set.drop_unused_older_than(0, 0), 0);
+        assert!(set.dirty.get());
+        assert_eq!(set.drop_unused(), 1);
+        assert!(!set.dirty.get());
+        assert_eq!(set.drop_unused(), 0);
+    }
+
+    #[test]
+    fn collect_used_elements() {
+        let mut set = IndexedHashSet::new();
+        let indices = [3, 1, 4, 1, 5]
+            .iter()
+            .filter_map(|i| set.insert(*i))
+            .collect::<Vec<_>>();
+        set.insert(9).unwrap();
+
+        let mut used = set.collect_used().into_vec();
+        used.sort_unstable();
+        assert_eq!(used, vec![1, 3, 4, 5]);
+        assert_eq!(indices.len(), 4);
+    }
+
+    #[test]
+    fn index_from_ref() {
+        let set = standard_set();
+        let olaf_ref = set.get_ref_by_hash("Olaf").unwrap();
+        let olaf = set.index_of_ref(olaf_ref).unwrap();
+        assert_eq!(set[&olaf], "Olaf");
+        assert_eq!(olaf.cnt(), 1);
+
+        // equal, but not stored in the set
+        let other = "Olaf".to_owned();
+        assert!(set.index_of_ref(&other).is_none());
+    }
+
+    #[test]
+    fn intern_rc() {
+        use std::rc::Rc;
+
+        let mut set = IndexedHashSet::new();
+        let olaf = Rc::new("Olaf".to_owned());
+        let _o = set.insert(Rc::clone(&olaf)).unwrap();
+        assert!(set.insert(Rc::new("Olaf".to_owned())).is_none());
+
+        let clone = set.get_rc_by_hash("Olaf").unwrap();
+        assert!(Rc::ptr_eq(&clone, &olaf));
+        // the caller's, the set's and the clone
+        assert_eq!(Rc::strong_count(&olaf), 3);
+        assert!(set.get_rc_by_hash("Harald").is_none());
+    }
+
+    #[test]
+    fn intern_arc() {
+        use std::sync::Arc;
+
+        let mut set = IndexedHashSet::<_, ArcCounter>::default();
+        let olaf = Arc::new("Olaf".to_owned());
+        let _o = set.insert(Arc::clone(&olaf)).unwrap();
+        assert!(set.insert(Arc::new("Olaf".to_owned())).is_none());
+
+        let clone = set.get_arc_by_hash("Olaf").unwrap();
+        assert!(Arc::ptr_eq(&clone, &olaf));
+        // the caller's, the set's and the clone
+        assert_eq!(Arc::strong_count(&olaf), 3);
+        assert!(set.get_arc_by_hash("Harald").is_none());
+    }
+
+    #[test]
+    fn truncate_unused() {
+        let mut set = IndexedHashSet::new();
+        let _first = set.insert(0).unwrap();
+        for i in 1..10 {
+            set.insert(i).unwrap();
+        }
+        assert_eq!(set.truncate_unused_to(10), 0);
+        assert_eq!(set.truncate_unused_to(3), 6);
+        assert_eq!(set.len(), 4);
+        // the most recently inserted unused entries are kept
+        assert!(set.contains_all(&[0, 7, 8, 9]));
+        assert_eq!(set.truncate_unused_to(0), 3);
+        assert_eq!(set.len(), 1);
+    }
+
+    #[test]
+    fn persistent_insert() {
+        let mut set = IndexedHashSet::new();
+        assert!(set.insert_persistent("Olaf"));
+        assert!(!set.insert_persistent("Olaf"));
+        set.insert("Harald").unwrap();
+        assert_eq!(set.drop_unused(), 1);
+        assert_eq!(set.get_cnt("Olaf"), Some(0));
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec![&"Olaf"]);
+
+        assert!(set.remove_by_hash("Olaf"));
+        assert!(!set.remove_by_hash("Olaf"));
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn pin_and_unpin() {
+        let mut set = standard_set();
+        assert!(set.pin("Olaf"));
+        assert!(!set.pin("Sven"));
+        assert_eq!(set.drop_unused(), 2);
+        assert!(set.contains("Olaf"));
+
+        assert!(set.unpin("Olaf"));
+        assert_eq!(set.drop_unused(), 1);
+        assert!(set.is_empty());
+        assert!(!set.unpin("Olaf"));
+    }
+
+    #[test]
+    fn reverse_iteration() {
+        let mut set = IndexedHashSet::new();
+        let indices = (0..6).map(|i| set.insert(i).unwrap()).collect::<Vec<_>>();
+        let used = indices.into_iter().step_by(2).collect::<Vec<_>>();
+
+        let forward = set.iter().copied().collect::<Vec<_>>();
+        let mut backward = set.iter().rev().copied().collect::<Vec<_>>();
+        assert_eq!(forward.len(), used.len());
+        backward.reverse();
+        assert_eq!(forward, backward);
+
+        let mut iter = set.iter();
+        let first = iter.next().copied();
+        let last = iter.next_back().copied();
+        assert_eq!(first, forward.first().copied());
+        assert_eq!(last, forward.last().copied());
+        assert_eq!(iter.count(), 1);
+    }
+
+    #[test]
+    fn index_and_ref_on_insert() {
+        let mut set = standard_set();
+        let (olaf, olaf_ref) = set.get_or_insert_entry("Olaf".to_owned());
+        assert_eq!(olaf_ref, "Olaf");
+        let (sven, sven_ref) = set.get_or_insert_entry("Sven".to_owned());
+        assert_eq!(sven_ref, "Sven");
+
+        assert_eq!(set.drop_unused(), 2);
+        assert_eq!(set[&olaf], "Olaf");
+        assert_eq!(set[&sven], "Sven");
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "usage count decremented below zero")]
+    fn count_underflow_panics() {
+        let cnt = RcCounter::new_handle();
+        RcCounter::inc(&cnt);
+        assert!(RcCounter::dec(&cnt));
+        RcCounter::dec(&cnt);
+    }
+
+    #[test]
+    #[cfg(not(debug_assertions))]
+    fn count_underflow_clamps() {
+        let cnt = RcCounter::new_handle();
+        assert!(!RcCounter::dec(&cnt));
+        assert_eq!(RcCounter::get(&cnt), 0);
+        let cnt = ArcCounter::new_handle();
+        ArcCounter::dec(&cnt);
+        assert_eq!(ArcCounter::get(&cnt), 0);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "usage count decremented below zero")]
+    fn count_underflow_panics_atomic() {
+        let cnt = ArcCounter::new_handle();
+        ArcCounter::dec(&cnt);
+    }
+
+    #[test]
+    fn histogram_of_counts() {
+        let set = standard_set();
+        let olaf = set.get_index_by_hash("Olaf").unwrap();
+        let _olaf2 = olaf.clone();
+        let _harald = set.get_index_by_hash("Harald").unwrap();
+
+        let histogram = set.count_histogram();
+        assert_eq!(
+            histogram.into_iter().collect::<Vec<_>>(),
+            vec![(0, 1), (1, 1), (2, 1)]
+        );
+    }
+
+    #[test]
+    fn replace_equal_element() {
+        #[derive(Debug)]
+        struct Named(u32, &'static str);
+
+        impl PartialEq for Named {
+            fn eq(&self, other: &Self) -> bool {
+                self.0 == other.0
+            }
+        }
+        impl Eq for Named {}
+        impl Hash for Named {
+            fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+                self.0.hash(state)
+            }
+        }
+
+        let mut set = IndexedHashSet::new();
+        let one = set.insert(Named(1, "Olaf")).unwrap();
+        let old = set.replace(Named(1, "Harald")).unwrap();
+        assert_eq!(old.1, "Olaf");
+        assert_eq!(set[&one].1, "Harald");
+        assert_eq!(set.get(&Named(1, "")).unwrap().1, "Harald");
+        assert_eq!(one.cnt(), 1);
+        assert_eq!(set.len(), 1);
+        set.assert_consistent();
+
+        assert!(set.replace(Named(2, "Eijnar")).is_none());
+        assert_eq!(set.get_cnt(&Named(2, "")), Some(0));
+    }
+
+    #[test]
+    fn insert_by_borrowed_key() {
+        let mut set = standard_set();
+        let calls = Cell::new(0);
+        let to_owned = |key: &str| {
+            calls.set(calls.get() + 1);
+            key.to_owned()
+        };
+
+        let olaf = set.get_or_insert_by("Olaf", || to_owned("Olaf"));
+        assert_eq!(calls.get(), 0);
+        assert_eq!(set[&olaf], "Olaf");
+
+        let sven = set.get_or_insert_by("Sven", || to_owned("Sven"));
+        assert_eq!(calls.get(), 1);
+        assert_eq!(set[&sven], "Sven");
+        assert_eq!(set.len(), 4);
+    }
+
+    #[test]
+    fn borrowed_elements() {
+        let names = ["Olaf".to_owned(), "Harald".to_owned(), "Olaf".to_owned()];
+        let mut set = IndexedHashSet::new();
+        let indices = names
+            .iter()
+            .map(|name| set.intern(name.as_str()).0)
+            .collect::<Vec<_>>();
+        assert_eq!(set.len(), 2);
+        assert_eq!(set[&indices[2]], "Olaf");
+        assert_eq!(set.get_cnt("Olaf"), Some(2));
+    }
+
+    #[test]
+    fn sort_indices_by_elements() {
+        let mut set = standard_set();
+        let mut indices = ["Olaf", "Harald", "Eijnar", "Olaf"]
+            .iter()
+            .map(|name| set.get_index_by_hash(*name).unwrap())
+            .collect::<Vec<_>>();
+        indices.sort_by(|a, b| set.cmp_indices(a, b).unwrap());
+        let names = indices
+            .iter()
+            .map(|idx| set[idx].as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["Eijnar", "Harald", "Olaf", "Olaf"]);
+
+        set.remove_by_hash("Eijnar");
+        assert_eq!(set.cmp_indices(&indices[0], &indices[2]), None);
+    }
+
+    #[test]
+    fn keep_group_alive() {
+        let mut set = standard_set();
+        let group = set.pin_group(vec!["Olaf", "Sven", "Harald"]);
+        assert_eq!(group.len(), 2);
+        assert_eq!(set.drop_unused(), 1);
+        assert!(set.contains_all(vec!["Olaf", "Harald"]));
+
+        drop(group);
+        assert_eq!(set.drop_unused(), 2);
+    }
+
+    #[test]
+    fn shrink_capacity() {
+        let mut set = IndexedHashSet::with_capacity(100);
+        for i in 0..10 {
+            set.insert(i).unwrap();
+        }
+        set.shrink_to(20);
+        assert!(set.capacity() >= 20);
+        assert!(set.capacity() < 100);
+        set.shrink_to(0);
+        assert!(set.capacity() >= set.len());
+
+        assert_eq!(set.drop_unused(), 10);
+        set.shrink_to_fit();
+        assert_eq!(set.capacity(), 0);
+    }
+
+    #[test]
+    fn weak_indexed_iteration() {
+        let mut set = standard_set();
+        let _olaf = set.get_index_by_hash("Olaf").unwrap();
+        let _harald = set.get_index_by_hash("Harald").unwrap();
+
+        let weak = set.iter_weak_indexed().collect::<Vec<_>>();
+        assert_eq!(weak.len(), 2);
+        assert_eq!(set.get_cnt("Olaf"), Some(1));
+        assert_eq!(set.get_cnt("Harald"), Some(1));
+
+        let (olaf_weak, _) = weak.iter().find(|(_, e)| *e == "Olaf").unwrap();
+        let olaf = olaf_weak.upgrade().unwrap();
+        drop(weak);
+        assert_eq!(olaf.cnt(), 2);
+        assert_eq!(set.drop_unused(), 1);
+    }
+
+    #[test]
+    fn mutate_by_hash() {
+        #[derive(Debug)]
+        struct Counted(&'static str, usize);
+
+        impl PartialEq for Counted {
+            fn eq(&self, other: &Self) -> bool {
+                self.0 == other.0
+            }
+        }
+        impl Eq for Counted {}
+        impl Hash for Counted {
+            fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+                self.0.hash(state)
+            }
+        }
+        impl Borrow<str> for Counted {
+            fn borrow(&self) -> &str {
+                self.0
+            }
+        }
+
+        let mut set = IndexedHashSet::new();
+        let olaf = set.insert(Counted("Olaf", 0)).unwrap();
+        for _ in 0..3 {
+            set.get_mut_by_hash("Olaf").unwrap().1 += 1;
+        }
+        assert!(set.get_mut_by_hash("Harald").is_none());
+        assert_eq!(set[&olaf].1, 3);
+        assert_eq!(set.get_ref_by_hash("Olaf").unwrap().1, 3);
+        set.assert_consistent();
+    }
+
+    #[test]
+    #[cfg(feature = "tracing")]
+    fn trace_drop_unused() {
+        use std::sync::{Arc, Mutex};
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata};
+
+        /// Records the `collected` field of all events.
+        struct Collected(Arc<Mutex<Vec<u64>>>);
+
+        impl Visit for &Collected {
+            fn record_u64(&mut self, field: &Field, value: u64) {
+                if field.name() == "collected" {
+                    self.0.lock().unwrap().push(value);
+                }
+            }
+            fn record_debug(&mut self, _: &Field, _: &dyn std::fmt::Debug) {}
+        }
+
+        impl tracing::Subscriber for Collected {
+            fn enabled(&self, _: &Metadata) -> bool {
+                true
+            }
+            fn new_span(&self, _: &Attributes) -> Id {
+                Id::from_u64(1)
+            }
+            fn record(&self, _: &Id, _: &Record) {}
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+            fn event(&self, event: &Event) {
+                event.record(&mut &*self);
+            }
+            fn enter(&self, _: &Id) {}
+            fn exit(&self, _: &Id) {}
+        }
+
+        let collected = Arc::new(Mutex::new(Vec::new()));
+        let subscriber = Collected(Arc::clone(&collected));
+        tracing::subscriber::with_default(subscriber, || {
+            let mut set = standard_set();
+            let _olaf = set.get_index_by_hash("Olaf").unwrap();
+            set.drop_unused();
+        });
+        assert_eq!(*collected.lock().unwrap(), vec![2]);
+    }
+
+    #[test]
+    fn collect_reserves_up_front() {
+        let n = 1000;
+        let expected = IndexedHashSet::<u32>::with_capacity(n);
+        let set = (0..n as u32).collect::<IndexedHashSet<_>>();
+        assert_eq!(set.len(), n);
+        assert_eq!(set.capacity(), expected.capacity());
+    }
+
+    #[test]
+    fn validated_new_index() {
+        let mut set = standard_set();
+        let olaf = set.get_index_by_hash("Olaf").unwrap();
+        let olaf2 = set.new_index(&olaf).unwrap();
+        assert_eq!(olaf.cnt(), 2);
+        assert_eq!(set[&olaf2], "Olaf");
+
+        let foreign = IndexedHashSet::<String>::new();
+        assert!(foreign.new_index(&olaf).is_none());
+
+        set.remove_by_hash("Olaf");
+        assert!(set.new_index(&olaf).is_none());
+    }
+
+    #[test]
+    fn retain_and_collect_indices() {
+        let mut set = standard_set();
+        let eijnar = set.get_index_by_hash("Eijnar").unwrap();
+        let kept = set.retain_collect(|name| name.contains('r'));
+        assert_eq!(kept.len(), 2);
+        assert!(!set.contains("Olaf"));
+        assert!(set.get_ref_by_index(&eijnar).is_some());
+        assert_eq!(eijnar.cnt(), 2);
+
+        drop(eijnar);
+        assert_eq!(set.drop_unused(), 0);
+        let mut names = kept.iter().map(|idx| set[idx].as_str()).collect::<Vec<_>>();
+        names.sort_unstable();
+        assert_eq!(names, vec!["Eijnar", "Harald"]);
+    }
+
+    #[test]
+    fn elements_round_trip() {
+        let set = standard_set();
+        let _olaf = set.get_index_by_hash("Olaf").unwrap();
+        let mut elements = set.into_elements();
+        elements.sort_unstable();
+        assert_eq!(
+            elements,
+            vec![
+                ("Eijnar".to_owned(), 0),
+                ("Harald".to_owned(), 0),
+                ("Olaf".to_owned(), 1)
+            ]
+        );
+
+        let set = IndexedHashSet::<_>::from_elements(elements.into_iter().map(|(e, _)| e));
+        assert_eq!(set.len(), 3);
+        assert!(set.contains_all(vec!["Olaf", "Eijnar", "Harald"]));
+        assert_eq!(set.get_cnt("Olaf"), Some(0));
+    }
+
+    #[test]
+    fn identity_of_references() {
+        let set = standard_set();
+        assert!(set.contains_value_ptr(set.get_ref_by_hash("Harald").unwrap()));
+        assert!(!set.contains_value_ptr(&"Harald".to_owned()));
+    }
+
+    #[test]
+    fn lazy_pinning() {
+        let mut set = standard_set();
+        let olaf = set.lazy_index_by_hash("Olaf").unwrap();
+        let harald = set.lazy_index_by_hash("Harald").unwrap();
+        assert!(set.lazy_index_by_hash("Sven").is_none());
+        assert_eq!(set.get_cnt("Olaf"), Some(0));
+
+        let pinned = olaf.pin(&set).unwrap();
+        assert_eq!(pinned.cnt(), 1);
+        assert!(olaf.pin(&standard_set()).is_none());
+
+        assert_eq!(set.drop_unused(), 2);
+        assert!(harald.pin(&set).is_none());
+        assert_eq!(set[&olaf.pin(&set).unwrap()], "Olaf");
+    }
+
+    #[test]
+    fn generation_advances_on_reuse() {
+        let mut set = IndexedHashSet::new();
+        let olaf = set.insert("Olaf").unwrap();
+        let slot = olaf.slot();
+        let generation = set.generation_of(slot).unwrap();
+        assert!(set.generation_of(slot + 1).is_none());
+
+        drop(olaf);
+        set.drop_unused();
+        assert!(set.generation_of(slot).is_none());
+
+        let harald = set.insert("Harald").unwrap();
+        assert_eq!(harald.slot(), slot);
+        assert!(set.generation_of(slot).unwrap() > generation);
+    }
+
+    #[test]
+    fn report_new_elements() {
+        let mut set = standard_set();
+        let report = set.extend_reporting(vec![
+            "Sven".to_owned(),
+            "Olaf".to_owned(),
+            "Knut".to_owned(),
+            "Sven".to_owned(),
+        ]);
+        assert_eq!(report, vec![true, false, true, false]);
+        assert_eq!(set.len(), 5);
+        assert_eq!(set.get_cnt("Knut"), Some(0));
+    }
+
+    #[test]
+    fn liveness_follows_counts() {
+        let set = standard_set();
+        let is_used = |name: &str| set.iter().any(|e| e == name);
+        let consistent = || {
+            set.iter_all()
+                .all(|(e, used)| used == (set.get_cnt(e.as_str()) != Some(0)))
+        };
+        assert!(consistent());
+
+        let olaf = set.get_index_by_hash("Olaf").unwrap();
+        let olaf2 = olaf.clone();
+        let weak = olaf.downgrade();
+        assert!(is_used("Olaf") && consistent());
+        drop(olaf);
+        assert!(is_used("Olaf") && consistent());
+        drop(olaf2);
+        assert!(!is_used("Olaf") && consistent());
+
+        let olaf = weak.upgrade().unwrap();
+        assert!(is_used("Olaf") && consistent());
+        drop(olaf);
+        assert!(!is_used("Olaf") && consistent());
+    }
+
+    #[test]
+    fn reserve_before_interning() {
+        let mut set = standard_set();
+        set.reserve_for_distinct(500);
+        let capacity = set.capacity();
+        assert!(capacity >= 503);
+
+        let indices = (0..1000)
+            .map(|i| set.get_or_insert(&format!("name {}", i % 500)))
+            .collect::<Vec<_>>();
+        assert_eq!(set.len(), 503);
+        assert_eq!(set.capacity(), capacity);
+        assert_eq!(indices[0].cnt(), 2);
+    }
+
+    #[test]
+    fn remove_via_index() {
+        let mut set = standard_set();
+        let olaf = set.get_index_by_hash("Olaf").unwrap();
+        let other = olaf.clone();
+        assert_eq!(set.remove_by_index(&olaf).unwrap(), "Olaf");
+        assert!(!set.contains("Olaf"));
+        assert_eq!(set.len(), 2);
+        assert_eq!(
+            set.get_ref_by_index_result(&other),
+            Err(IndexError::Collected)
+        );
+        assert!(set.remove_by_index(&other).is_none());
+    }
+
+    #[test]
+    fn get_like_hash_set() {
+        let set = standard_set();
+        assert_eq!(set.get("Olaf"), Some(&"Olaf".to_owned()));
+        assert_eq!(set.get(&"Harald".to_owned()), Some(&"Harald".to_owned()));
+        assert_eq!(set.get("Sven"), None);
+    }
+
+    #[test]
+    fn lazy_drain() {
+        let mut set = IndexedHashSet::new();
+        let indices = (0..10).map(|i| set.insert(i).unwrap()).collect::<Vec<_>>();
+
+        let drained = set.drain_where(|i| i % 2 == 0).take(2).collect::<Vec<_>>();
+        assert_eq!(drained.len(), 2);
+        assert_eq!(set.len(), 8);
+        for i in &drained {
+            assert!(!set.contains(i));
+        }
+        assert_eq!(set.count_where(|i| i % 2 == 0), 3);
+        let unresolved = indices
+            .iter()
+            .filter(|idx| set.get_ref_by_index(idx).is_none())
+            .count();
+        assert_eq!(unresolved, 2);
+
+        assert_eq!(set.drain_where(|i| i % 2 == 0).count(), 3);
+        assert_eq!(set.len(), 5);
+    }
+
+    #[test]
+    fn custom_hasher() {
+        let mut set = IndexedHashSet::<_, RcCounter, _>::with_capacity_and_hasher(8, Seeded(42));
+        assert_eq!(set.hasher(), &Seeded(42));
+        assert!(set.capacity() >= 8);
+        let olaf = set.insert("Olaf").unwrap();
+        assert_eq!(set.get_index_by_hash("Olaf").unwrap().slot(), olaf.slot());
+        assert_eq!(set.hash_of("Olaf"), Seeded(42).hash_one("Olaf"));
+
+        let other = IndexedHashSet::<&str, RcCounter, _>::with_hasher(Seeded(42));
+        assert_eq!(set.hash_of("Olaf"), other.hash_of("Olaf"));
+    }
+
+    #[test]
+    fn entry_or_insert() {
+        let mut set = standard_set();
+        let olaf = set.entry("Olaf".to_owned()).or_insert();
+        assert_eq!(set[&olaf], "Olaf");
+        let sven = set.entry("Sven".to_owned()).or_insert();
+        assert_eq!(set[&sven], "Sven");
+        assert_eq!(set.len(), 4);
+
+        match set.entry("Olaf".to_owned()) {
+            entry::Entry::Occupied(occupied) => assert_eq!(occupied.get(), "Olaf"),
+            entry::Entry::Vacant(_) => panic!("Olaf is present"),
+        }
+    }
+
+    #[test]
+    fn entry_or_insert_with() {
+        let mut set = standard_set();
+        let calls = Cell::new(0);
+        let factory = |name: &str| {
+            calls.set(calls.get() + 1);
+            name.to_owned()
+        };
 
-        let a_idx = self.arena.insert(entry);
-        self.map.insert(inner_ref, a_idx);
+        let harald = set
+            .entry("Harald".to_owned())
+            .or_insert_with(|| factory("Harald"));
+        assert_eq!(calls.get(), 0);
+        assert_eq!(harald.cnt(), 1);
 
-        RcIndex::new(a_idx, cnt_handle)
+        let knut = set
+            .entry("Knut".to_owned())
+            .or_insert_with(|| factory("Knut"));
+        assert_eq!(calls.get(), 1);
+        assert_eq!(set[&knut], "Knut");
     }
-    /// Drop all entries whose `usage_cnt` is zero.
-    pub fn drop_unused(&mut self) -> usize {
-        // tell Rust that both mutable borrows are distinct.
-        let arena = &mut self.arena;
-        let map = &mut self.map;
-
-        let before = arena.len();
 
-        arena.retain(|_, entry| {
-            if entry.cnt() == 0 {
-                map.remove(entry.elem().wrap());
-                false
-            } else {
-                true
+    #[test]
+    fn entry_types_default_counter() {
+        fn insert_vacant(entry: entry::Entry<'_, String>) -> Option<RcIndex> {
+            match entry {
+                entry::Entry::Occupied(_) => None,
+                entry::Entry::Vacant(vacant) => Some(vacant.insert()),
             }
-        });
+        }
 
-        before - arena.len()
+        let mut set = standard_set();
+        assert!(insert_vacant(set.entry("Olaf".to_owned())).is_none());
+        let sven = insert_vacant(set.entry("Sven".to_owned())).unwrap();
+        assert_eq!(set[&sven], "Sven");
     }
-    /// Iterates over all elements in the set with `usage_cnt != 0`.
-    pub fn iter(&self) -> impl Iterator<Item = &T> {
-        self.arena
+
+    #[test]
+    fn fallible_reserve() {
+        let mut set = standard_set();
+        assert_eq!(
+            set.try_reserve(usize::MAX),
+            Err(TryReserveError::CapacityOverflow)
+        );
+        assert!(set.try_reserve(usize::MAX / 2).is_err());
+        assert_eq!(set.len(), 3);
+
+        assert_eq!(set.try_reserve(100), Ok(()));
+        assert!(set.capacity() >= 103);
+    }
+
+    #[test]
+    fn canonical_key() {
+        let set = standard_set();
+        let query = "Olaf".to_owned();
+        let key = set.get_key(query.as_str()).unwrap();
+        assert_eq!(key, "Olaf");
+        assert_ne!(key.as_ptr(), query.as_ptr());
+        assert_eq!(set.get_key("Olaf").unwrap().as_ptr(), key.as_ptr());
+        assert!(set.get_key("Sven").is_none());
+    }
+
+    #[test]
+    fn stable_addresses() {
+        let mut set = IndexedHashSet::new();
+        let first = set.insert(0u64).unwrap();
+        let addr = set.stable_ref_by_index(&first).unwrap() as *const u64;
+        let capacity = set.capacity();
+
+        let indices = (1..1000)
+            .map(|i| set.insert(i).unwrap())
+            .collect::<Vec<_>>();
+        assert!(set.capacity() > capacity);
+        assert_eq!(set.stable_ref_by_index(&first).unwrap() as *const u64, addr);
+
+        drop(indices);
+        set.drop_unused();
+        assert_eq!(set.stable_ref_by_index(&first).unwrap() as *const u64, addr);
+    }
+
+    #[test]
+    fn rehash_keeps_entries() {
+        let mut set = IndexedHashSet::new();
+        let kept = (0..1000)
+            .filter_map(|i| {
+                let idx = set.insert(i).unwrap();
+                if i % 10 == 0 {
+                    Some(idx)
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(set.drop_unused(), 900);
+
+        let capacity = set.capacity();
+        set.rehash();
+        assert_eq!(set.capacity(), capacity);
+        assert_eq!(set.len(), 100);
+        for idx in &kept {
+            let elem = set[idx];
+            assert_eq!(set.get_index_by_hash(&elem).unwrap().slot(), idx.slot());
+        }
+        assert!(!set.contains(&1));
+    }
+
+    #[test]
+    // the order ignores the interior mutable count
+    #[allow(clippy::mutable_key_type)]
+    fn indices_as_btree_keys() {
+        let mut set = IndexedHashSet::new();
+        let indices = (0..5).map(|i| set.insert(i).unwrap()).collect::<Vec<_>>();
+        let olaf = indices[2].clone();
+        assert_eq!(olaf, indices[2]);
+        assert_ne!(olaf, indices[3]);
+
+        let map = indices
             .iter()
-            .filter_map(|(_, e)| if e.cnt() != 0 { Some(e.elem()) } else { None })
+            .rev()
+            .map(|idx| (idx.clone(), set[idx]))
+            .collect::<BTreeMap<_, _>>();
+        let slots = map.keys().map(RcIndex::slot).collect::<Vec<_>>();
+        let mut sorted = slots.clone();
+        sorted.sort_unstable();
+        assert_eq!(slots, sorted);
+        assert_eq!(map[&olaf], 2);
     }
-    /// Returns the respective `RcIndex` for an index of the arena.
-    ///
-    /// # Panics
-    ///
-    /// This panics if the arena index is not present. However, since these
-    /// kind of indices are only used internally this should never be the case.
-    fn aidx_to_rcidx(&self, a_idx: AIndex) -> RcIndex {
-        let entry = &self.arena[a_idx];
-        let handle = entry.cnt_handle();
-        RcIndex::new(a_idx, handle)
+
+    #[test]
+    fn fallible_get_or_insert() {
+        let mut set = standard_set();
+        let capacity = set.capacity();
+        let olaf = set.try_get_or_insert("Olaf".to_owned()).unwrap();
+        assert_eq!(set[&olaf], "Olaf");
+        assert_eq!(set.get_cnt("Olaf"), Some(1));
+        assert_eq!(set.capacity(), capacity);
+
+        let (sven, err) = set
+            .try_get_or_insert_with("Sven".to_owned(), |_| {
+                Err(TryReserveError::CapacityOverflow)
+            })
+            .unwrap_err();
+        assert_eq!(sven, "Sven");
+        assert_eq!(err, TryReserveError::CapacityOverflow);
+        assert!(!set.contains("Sven"));
+        assert_eq!(set.len(), 3);
+        set.assert_consistent();
+
+        let sven = set.try_get_or_insert("Sven".to_owned()).unwrap();
+        assert_eq!(set[&sven], "Sven");
+        assert_eq!(set.len(), 4);
     }
-}
 
-impl<T: 'static> Default for IndexedHashSet<T> {
-    fn default() -> Self {
-        Self {
-            arena: Default::default(),
-            map: Default::default(),
+    #[test]
+    fn raw_pointers() {
+        let set = standard_set();
+        let olaf = set.get_index_by_hash("Olaf").unwrap();
+        let ptr = set.raw_ptr_by_index(&olaf).unwrap();
+        assert_eq!(ptr, set.get_ref_by_index(&olaf).unwrap() as *const String);
+        assert!(IndexedHashSet::<String>::new()
+            .raw_ptr_by_index(&olaf)
+            .is_none());
+    }
+
+    #[test]
+    fn cnt_by_hash_and_index_agree() {
+        let mut set = IndexedHashSet::<String>::new();
+        let olaf = set.insert("Olaf".to_owned()).unwrap();
+        assert_eq!(set.get_cnt("Olaf"), set.get_cnt_by_index(&olaf));
+
+        let clones = vec![olaf.clone(), olaf.clone()];
+        assert_eq!(set.get_cnt("Olaf"), Some(3));
+        assert_eq!(set.get_cnt("Olaf"), set.get_cnt_by_index(&olaf));
+
+        drop(clones);
+        assert_eq!(set.get_cnt("Olaf"), Some(1));
+        assert_eq!(set.get_cnt("Olaf"), set.get_cnt_by_index(&olaf));
+
+        let other = IndexedHashSet::<String>::new();
+        assert!(other.get_cnt_by_index(&olaf).is_none());
+    }
+
+    #[test]
+    fn retain_keeps_insertion_order() {
+        let mut set = IndexedHashSet::<u32>::new();
+        let mut indices = (0..10).map(|i| set.insert(i).unwrap()).collect::<Vec<_>>();
+        // free some slots in the middle, so new entries reuse them
+        indices.drain(3..6);
+        assert_eq!(set.drop_unused(), 3);
+        indices.extend((10..13).map(|i| set.insert(i).unwrap()));
+
+        let kept = set.retain_collect(|i| i % 2 == 0);
+        drop(indices);
+        assert_eq!(
+            set.iter_ordered().copied().collect::<Vec<_>>(),
+            vec![0, 2, 6, 8, 10, 12]
+        );
+        drop(kept);
+    }
+
+    #[test]
+    fn empty_set_skips_hashing() {
+        let set = IndexedHashSet::<CountHash>::new();
+        let key = CountHash(1);
+        hash_calls();
+        assert!(!set.contains(&key));
+        assert!(set.get(&key).is_none());
+        assert!(set.get_cnt(&key).is_none());
+        assert!(set.get_index_by_hash(&key).is_none());
+        assert!(set.lazy_index_by_hash(&key).is_none());
+        assert!(!set.contains_value_ptr(&key));
+        assert_eq!(hash_calls(), 0);
+
+        let mut set = set;
+        assert!(!set.pin(&key));
+        assert!(!set.unpin(&key));
+        assert!(!set.remove_by_hash(&key));
+        let rcs = IndexedHashSet::<std::rc::Rc<CountHash>>::new();
+        assert!(rcs.get_rc_by_hash(&key).is_none());
+        let arcs = IndexedHashSet::<std::sync::Arc<CountHash>, ArcCounter>::default();
+        assert!(arcs.get_arc_by_hash(&key).is_none());
+        assert_eq!(hash_calls(), 0);
+
+        let _idx = set.insert(CountHash(2)).unwrap();
+        hash_calls();
+        assert!(!set.contains(&key));
+        assert_eq!(hash_calls(), 1);
+    }
+
+    #[test]
+    fn observe_count() {
+        let mut set = IndexedHashSet::<String>::new();
+        let olaf = set.insert("Olaf".to_owned()).unwrap();
+        let again = olaf.clone();
+        let observer = set.count_observer("Olaf").unwrap();
+        assert!(set.count_observer("Harald").is_none());
+        assert_eq!(observer.count(), Some(2));
+
+        drop(olaf);
+        drop(again);
+        assert_eq!(observer.count(), Some(0));
+        assert_eq!(set.drop_unused(), 1);
+        assert_eq!(observer.count(), None);
+    }
+
+    #[test]
+    fn append_moves_union() {
+        let mut set = standard_set();
+        let olaf = set.get_index_by_hash("Olaf").unwrap();
+        let mut other = IndexedHashSet::<String>::new();
+        let other_olaf = other.insert("Olaf".to_owned()).unwrap();
+        let _sven = other.insert("Sven".to_owned()).unwrap();
+        let capacity = other.capacity();
+
+        set.append(&mut other);
+        assert!(other.is_empty());
+        assert_eq!(other.capacity(), capacity);
+        assert!(other.get_ref_by_index(&other_olaf).is_none());
+
+        assert_eq!(set.len(), 4);
+        assert!(set.contains_all(["Olaf", "Eijnar", "Harald", "Sven"]));
+        assert_eq!(set[&olaf], "Olaf");
+        assert_eq!(set.get_cnt("Olaf"), Some(1));
+    }
+
+    #[test]
+    fn panicking_append_keeps_invariants() {
+        let mut set = IndexedHashSet::<PanicHash>::new();
+        let _zero = set.insert(PanicHash(0)).unwrap();
+        let mut other = IndexedHashSet::<PanicHash>::new();
+        let _indices = (1..4)
+            .map(|i| other.insert(PanicHash(i)).unwrap())
+            .collect::<Vec<_>>();
+
+        PANIC_ON.with(|p| p.set(Some(2)));
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            set.append(&mut other);
+        }));
+        PANIC_ON.with(|p| p.set(None));
+        assert!(result.is_err());
+
+        set.assert_consistent();
+        other.assert_consistent();
+        assert!(other.is_empty());
+        assert!(set.contains(&PanicHash(0)));
+        assert_eq!(other.drop_unused(), 0);
+    }
+
+    #[test]
+    fn absent_dead_and_live_states() {
+        let mut set = standard_set();
+        assert!(matches!(set.index_state("Sven"), IndexState::Absent));
+
+        let olaf = match set.index_state("Olaf") {
+            IndexState::Dead(idx) => idx,
+            state => panic!("unexpected {:?}", state),
+        };
+        match set.index_state("Olaf") {
+            IndexState::Live(idx) => assert_eq!(idx, olaf),
+            state => panic!("unexpected {:?}", state),
         }
+
+        assert!(matches!(set.index_state("Harald"), IndexState::Dead(_)));
+        assert_eq!(set.drop_unused(), 2);
+        assert!(matches!(set.index_state("Harald"), IndexState::Absent));
     }
-}
 
-/// Allows to access the set like `set[&rc_idx]`.
-///
-/// This panics if the `RcIndex` used is not from this `IndexedHashSet`.
-impl<'a, T> std::ops::Index<&'a RcIndex> for IndexedHashSet<T>
-where
-    T: 'static + Eq + Hash,
-{
-    type Output = T;
+    #[test]
+    fn refresh_reinserted_entry() {
+        let mut set = standard_set();
+        let mut olaf = set.get_index_by_hash("Olaf").unwrap();
+        assert!(!set.refresh(&mut olaf, "Olaf"));
 
-    fn index(&self, index: &'a RcIndex) -> &Self::Output {
-        self.get_ref_by_index(index).unwrap()
+        assert!(set.remove_by_hash("Olaf"));
+        assert!(!set.refresh(&mut olaf, "Olaf"));
+        let again = set.insert("Olaf".to_owned()).unwrap();
+        assert!(set.get_ref_by_index(&olaf).is_none());
+
+        assert!(set.refresh(&mut olaf, "Olaf"));
+        assert_eq!(set[&olaf], "Olaf");
+        assert_eq!(olaf, again);
+        assert_eq!(again.cnt(), 2);
+        set.assert_consistent();
     }
-}
 
-/// The `!Send` internal references are only used internally. Therefore, this
-/// type is safe to be `Send`.
-unsafe impl<T> Send for IndexedHashSet<T> {}
+    #[test]
+    fn consistent_after_growth() {
+        let mut set = IndexedHashSet::<u32>::with_capacity(2);
+        let mut indices = (0..100).map(|i| set.insert(i).unwrap()).collect::<Vec<_>>();
+        set.assert_consistent();
 
-/// A reference-counted index to an entry of the set.
-#[derive(Debug)]
-pub struct RcIndex {
-    /// Original index into the arena.
-    inner: AIndex,
-    /// Usage count. Incremented at index construction and decremented at drop.
-    cnt: Rc<RefCell<usize>>,
-}
+        indices.retain(|idx| set[idx] < 34);
+        assert_eq!(set.used_len(), 34);
+        set.assert_consistent();
+        assert_eq!(set.drop_unused(), 66);
+        assert!(set.insert_persistent(1000));
+        set.remove_by_index(&indices[0]).unwrap();
+        set.assert_consistent();
+        assert_eq!(set.used_len(), 34);
+    }
 
-impl RcIndex {
-    /// Creates a new reference-counted index.
-    ///
-    /// On creation the `usage_cnt` is incremented.
-    fn new(idx: AIndex, cnt_handle: Rc<RefCell<usize>>) -> Self {
-        {
-            let mut cnt = cnt_handle.borrow_mut();
-            *cnt += 1;
+    #[test]
+    fn compaction_maps_raw_indices() {
+        let mut set = IndexedHashSet::<u32>::new();
+        let raw = (0..10)
+            .map(|i| set.insert(i).unwrap().into_raw())
+            .collect::<Vec<_>>();
+        assert!(set.pin(&9));
+
+        let moved = set.retain_and_compact(|i| *i >= 5).unwrap();
+        assert_eq!(moved.len(), 5);
+        assert_eq!(set.len(), 5);
+        set.assert_consistent();
+        for (i, old) in raw.iter().enumerate() {
+            assert!(set.get_ref_by_raw(*old).is_none());
+            match moved.get(*old) {
+                Some(new) => assert_eq!(set.get_ref_by_raw(new), Some(&(i as u32))),
+                None => assert!(i < 5),
+            }
         }
-        Self {
-            inner: idx,
-            cnt: cnt_handle,
+        // the survivors occupy the first slots
+        assert!(moved.iter().all(|(_, new)| new.into_raw_parts().0 < 5));
+        assert_eq!(set.get_cnt(&9), Some(0));
+        assert_eq!(set.drop_unused(), 4);
+    }
+
+    #[test]
+    fn compaction_refused_while_indexed() {
+        let mut set = standard_set();
+        let olaf = set.get_index_by_hash("Olaf").unwrap();
+        assert!(set.retain_and_compact(|_| false).is_none());
+        assert_eq!(set.len(), 3);
+        assert_eq!(set[&olaf], "Olaf");
+
+        drop(olaf);
+        assert!(set.retain_and_compact(|_| false).unwrap().is_empty());
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn non_default_hasher() {
+        let mut set = IndexedHashSet::<String, RcCounter, _>::with_hasher(Seeded(7));
+        let olaf = set.insert("Olaf".to_owned()).unwrap();
+        let harald = set.get_or_insert(&"Harald".to_owned());
+        assert!(set.insert("Olaf".to_owned()).is_none());
+        assert_eq!(set.get("Harald"), Some(&set[&harald]));
+        assert_eq!(set.get_index_by_hash("Olaf"), Some(olaf.clone()));
+        assert_eq!(set.iter().count(), 2);
+
+        drop(harald);
+        assert_eq!(set.drop_unused(), 1);
+        assert!(!set.contains("Harald"));
+        assert_eq!(set[&olaf], "Olaf");
+    }
+
+    #[test]
+    fn count_all_references() {
+        let mut set = standard_set();
+        assert_eq!(set.total_references(), 0);
+        let olaf = set.get_index_by_hash("Olaf").unwrap();
+        let clones = vec![olaf.clone(), olaf.clone()];
+        let sven = set.insert("Sven".to_owned()).unwrap();
+        assert!(set.pin("Harald"));
+        assert_eq!(set.total_references(), 4);
+        assert_eq!(set.used_len(), 3);
+
+        drop(clones);
+        drop(sven);
+        assert_eq!(set.total_references(), 1);
+        drop(olaf);
+        assert_eq!(set.total_references(), 0);
+    }
+
+    #[test]
+    fn intern_static_strings() {
+        let mut set = IndexedHashSet::<&'static str>::new();
+        let olaf = set.intern_static("Olaf");
+        let harald = set.intern_static("Harald");
+        assert_eq!(set.intern_static("Olaf"), olaf);
+        assert_eq!(set.len(), 2);
+
+        assert_eq!(set.get("Harald"), Some(&"Harald"));
+        assert_eq!(set.get_index_by_hash("Olaf"), Some(olaf.clone()));
+        assert_eq!(set.get_or_insert(&"Harald"), harald);
+        assert!(set.insert("Olaf").is_none());
+        assert_eq!(set[&olaf], "Olaf");
+    }
+
+    #[test]
+    fn report_collection() {
+        let mut set = standard_set();
+        let _olaf = set.get_index_by_hash("Olaf").unwrap();
+        let report = set.drop_unused_report();
+        assert_eq!(report.removed, 2);
+        assert_eq!(report.entries_before, 3);
+        assert_eq!(report.entries_after, 1);
+        assert_eq!(report.entries_before - report.removed, report.entries_after);
+        let per_entry = std::mem::size_of::<Entry<String>>()
+            + std::mem::size_of::<String>()
+            + std::mem::size_of::<Cell<usize>>()
+            + 2 * std::mem::size_of::<usize>();
+        assert_eq!(report.approx_bytes_freed, 2 * per_entry);
+
+        let report = set.drop_unused_report();
+        assert_eq!(report.removed, 0);
+        assert_eq!(report.approx_bytes_freed, 0);
+    }
+
+    #[test]
+    fn batch_indices_follow_input() {
+        let mut set = standard_set();
+        let names = ["Sven", "Olaf", "Sven", "Gudrun"];
+        let indices = set.insert_batch(names.iter().map(|n| n.to_string()));
+        assert_eq!(indices.len(), names.len());
+        for (idx, name) in indices.iter().zip(&names) {
+            assert_eq!(set[idx], *name);
         }
+        assert_eq!(indices[0], indices[2]);
+        assert_eq!(set.len(), 5);
+        assert_eq!(set.get_cnt("Sven"), Some(2));
+        assert_eq!(set.drop_unused(), 2);
     }
-    /// Get the usage count of the element.
-    pub fn cnt(&self) -> usize {
-        *(*self.cnt).borrow()
+
+    #[test]
+    fn strong_and_weak_counts() {
+        let set = standard_set();
+        let olaf = set.get_index_by_hash("Olaf").unwrap();
+        assert_eq!(set.ref_counts_by_index(&olaf), Some((1, 0)));
+
+        let clone = olaf.clone();
+        let weak = olaf.downgrade();
+        let observer = set.count_observer("Olaf").unwrap();
+        assert_eq!(set.ref_counts_by_index(&olaf), Some((2, 2)));
+
+        drop(clone);
+        drop(observer);
+        assert_eq!(set.ref_counts_by_index(&olaf), Some((1, 1)));
+        assert!(weak.upgrade().is_some());
+        assert_eq!(set.ref_counts_by_index(&olaf), Some((1, 1)));
+        assert!(IndexedHashSet::<String>::new()
+            .ref_counts_by_index(&olaf)
+            .is_none());
     }
-}
 
-impl Clone for RcIndex {
-    fn clone(&self) -> Self {
-        let mut cnt = self.cnt.borrow_mut();
-        *cnt += 1;
-        Self {
-            inner: self.inner,
-            cnt: self.cnt.clone(),
+    #[test]
+    fn full_lookup_is_consistent() {
+        let set = standard_set();
+        let olaf = set.get_index_by_hash("Olaf").unwrap();
+        let (elem, idx, cnt) = set.lookup_full("Olaf").unwrap();
+        assert_eq!(elem, "Olaf");
+        assert!(std::ptr::eq(elem, &set[&olaf]));
+        assert_eq!(idx, olaf);
+        assert_eq!(cnt, 2);
+        assert_eq!(idx.cnt(), cnt);
+        assert!(set.lookup_full("Sven").is_none());
+    }
+
+    #[test]
+    fn no_growth_within_capacity() {
+        for n in [0, 1, 7, 100] {
+            let mut set = IndexedHashSet::<usize>::with_capacity(n);
+            let arena_capacity = set.arena.capacity();
+            let map_capacity = set.map.capacity();
+            assert!(set.capacity() >= n);
+
+            let _indices = (0..n).map(|i| set.insert(i).unwrap()).collect::<Vec<_>>();
+            assert_eq!(set.arena.capacity(), arena_capacity);
+            assert_eq!(set.map.capacity(), map_capacity);
         }
     }
-}
 
-impl Drop for RcIndex {
-    fn drop(&mut self) {
-        let mut cnt = self.cnt.borrow_mut();
-        *cnt -= 1;
+    #[test]
+    fn iter_yields_counts() {
+        let mut set = standard_set();
+        let olaf = set.get_index_by_hash("Olaf").unwrap();
+        let _olaf2 = olaf.clone();
+        let _harald = set.get_index_by_hash("Harald").unwrap();
+        assert!(set.pin("Eijnar"));
+
+        let mut counts = set.iter_with_cnt().collect::<Vec<_>>();
+        counts.sort();
+        assert_eq!(
+            counts,
+            vec![
+                (&"Eijnar".to_owned(), 0),
+                (&"Harald".to_owned(), 1),
+                (&"Olaf".to_owned(), 2)
+            ]
+        );
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn remove_single_unused() {
+        let mut set = standard_set();
+        let olaf = set.get_index_by_hash("Olaf").unwrap();
+        assert!(set.pin("Eijnar"));
 
-    /// Set with three entries with each usage count equal to zero.
-    fn standard_set() -> IndexedHashSet<String> {
-        let mut set = IndexedHashSet::new();
-        set.insert("Olaf".to_owned()).unwrap();
-        set.insert("Eijnar".to_owned()).unwrap();
-        set.insert("Harald".to_owned()).unwrap();
-        set
+        assert!(!set.remove_if_unused("Olaf"));
+        assert!(!set.remove_if_unused("Eijnar"));
+        assert!(!set.remove_if_unused("Sven"));
+        assert!(set.remove_if_unused("Harald"));
+        assert!(!set.contains("Harald"));
+        assert_eq!(set.len(), 2);
+        set.assert_consistent();
+
+        drop(olaf);
+        assert!(set.remove_if_unused("Olaf"));
+        assert_eq!(set.len(), 1);
+
+        let mut empty = IndexedHashSet::<CountHash>::new();
+        hash_calls();
+        assert!(!empty.remove_if_unused(&CountHash(1)));
+        assert_eq!(hash_calls(), 0);
     }
 
     #[test]
-    fn unused_entries() {
+    fn validated_clone_rejects_stale() {
         let mut set = standard_set();
-        assert_eq!(set.len(), 3);
-        assert_eq!(set.drop_unused(), 3);
-        assert_eq!(set.len(), 0);
+        let olaf = set.get_index_by_hash("Olaf").unwrap();
+        let clone = set.new_index(&olaf).unwrap();
+        assert_eq!(clone, olaf);
+        assert_eq!(olaf.cnt(), 2);
+
+        set.remove_by_index(&olaf).unwrap();
+        // reuse the slot of the removed entry
+        let sven = set.insert("Sven".to_owned()).unwrap();
+        assert_eq!(sven.slot(), olaf.slot());
+        assert!(set.new_index(&olaf).is_none());
+        let stale = olaf.clone();
+        assert!(set.get_ref_by_index(&stale).is_none());
     }
 
     #[test]
-    fn usage_cnt() {
-        let mut set = IndexedHashSet::new();
-        let o1 = set.insert("Olaf".to_owned()).unwrap();
-        assert_eq!(o1.cnt(), 1);
-        let _o2 = set.get_index_by_hash("Olaf").unwrap();
-        assert_eq!(o1.cnt(), 2);
-        {
-            let _o3 = o1.clone();
-            assert_eq!(o1.cnt(), 3);
+    fn keep_recent_unused() {
+        let mut set = IndexedHashSet::<u32>::new();
+        let used = set.insert(0).unwrap();
+        for i in 1..=6 {
+            let _ = set.insert(i);
         }
-        assert_eq!(o1.cnt(), 2);
+        // touching an old entry does not make it recent
+        drop(set.get_index_by_hash(&1).unwrap());
+
+        assert_eq!(set.truncate_unused_to(2), 4);
+        let mut left = set.iter_all().map(|(i, _)| *i).collect::<Vec<_>>();
+        left.sort_unstable();
+        assert_eq!(left, vec![0, 5, 6]);
+        assert_eq!(set.truncate_unused_to(2), 0);
+        assert_eq!(set[&used], 0);
+    }
+
+    #[test]
+    fn store_public_raw_index() {
+        struct Store {
+            raw: ArenaIndex,
+        }
+
+        let set = standard_set();
+        let store = Store {
+            raw: set.get_index_by_hash("Olaf").unwrap().into_raw(),
+        };
+        assert_eq!(set.get_ref_by_raw(store.raw).unwrap(), "Olaf");
+        assert_eq!(set[&set.promote(store.raw).unwrap()], "Olaf");
+    }
+
+    #[test]
+    fn extend_reserves_up_front() {
+        let mut set = standard_set();
+        set.extend((0..1000).map(|i| i.to_string()));
+        assert!(set.capacity() >= 1003);
+
+        // growing step by step would overshoot the single reservation
+        let mut expected = standard_set();
+        expected.reserve(1000);
+        assert_eq!(set.arena.capacity(), expected.arena.capacity());
+        assert_eq!(set.capacity(), expected.capacity());
+    }
+
+    #[test]
+    fn index_on_demand() {
+        let set = standard_set();
+        let len = set.with_entry("Olaf", |elem, _| elem.len());
+        assert_eq!(len, Some(4));
+        assert_eq!(set.get_cnt("Olaf"), Some(0));
+
+        let harald = set
+            .with_entry("Harald", |elem, index| {
+                assert_eq!(elem, "Harald");
+                index()
+            })
+            .unwrap();
+        assert_eq!(set.get_cnt("Harald"), Some(1));
+        assert_eq!(set[&harald], "Harald");
+        assert!(set.with_entry("Sven", |_, index| index()).is_none());
+    }
+
+    #[test]
+    fn into_iter_keeps_raw_indices() {
+        let set = standard_set();
+        let expected = set
+            .iter_all()
+            .map(|(elem, _)| {
+                let raw = set.get_index_by_hash(elem).unwrap().into_raw();
+                (raw, elem.clone())
+            })
+            .collect::<std::collections::HashMap<_, _>>();
+
+        let moved = set
+            .into_iter_with_raw()
+            .collect::<std::collections::HashMap<_, _>>();
+        assert_eq!(moved.len(), 3);
+        assert_eq!(moved, expected);
+    }
+
+    #[test]
+    fn panicking_rehash_keeps_invariants() {
+        let mut set = IndexedHashSet::<PanicHash>::new();
+        let mut indices = vec![set.insert(PanicHash(0)).unwrap()];
+        let mut i = 1;
+        while set.len() < set.map.capacity() {
+            indices.push(set.insert(PanicHash(i)).unwrap());
+            i += 1;
+        }
+
+        // the next insertion grows the map which rehashes `PanicHash(0)`
+        PANIC_ON.with(|p| p.set(Some(0)));
+        let len = set.len();
+        let next_seq = set.next_seq;
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _ = set.insert(PanicHash(i));
+        }));
+        PANIC_ON.with(|p| p.set(None));
+        assert!(result.is_err());
+
+        assert_eq!(set.len(), len);
+        assert_eq!(set.next_seq, next_seq);
+        assert!(!set.contains(&PanicHash(i)));
+        set.assert_consistent();
+        assert!(set.insert(PanicHash(i)).is_some());
+        set.assert_consistent();
+    }
+
+    #[test]
+    fn for_each_like_iter() {
+        let set = standard_set();
+        let _olaf = set.get_index_by_hash("Olaf").unwrap();
+        let _harald = set.get_index_by_hash("Harald").unwrap();
+
+        let mut visited = Vec::new();
+        set.for_each_used(|elem| visited.push(elem));
+        assert_eq!(visited.len(), 2);
+        assert_eq!(visited, set.iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn lookup_path_buf_by_path() {
+        use std::path::{Path, PathBuf};
+
+        let mut set = IndexedHashSet::<PathBuf>::new();
+        let hosts = set.insert(PathBuf::from("/etc/hosts")).unwrap();
+        let query: &Path = Path::new("/etc/hosts");
+        assert_eq!(set.get_index_by_hash(query), Some(hosts.clone()));
+        assert_eq!(set.get(query), Some(&set[&hosts]));
+        assert_eq!(set.get_cnt(Path::new("/etc/hosts")), Some(1));
+        assert!(!set.contains(Path::new("/etc/passwd")));
+    }
+
+    #[test]
+    fn cumulative_gc_metrics() {
+        let mut set = standard_set();
+        let _olaf = set.get_index_by_hash("Olaf").unwrap();
+        assert!(set.insert("Olaf".to_owned()).is_none());
+        assert_eq!(
+            set.gc_metrics(),
+            GcMetrics {
+                total_inserts: 3,
+                ..GcMetrics::default()
+            }
+        );
+
+        assert_eq!(set.drop_unused(), 2);
+        let _ = set.insert("Sven".to_owned());
+        let _ = set.insert("Gudrun".to_owned());
+        assert_eq!(set.drop_unused(), 2);
+        assert_eq!(set.drop_unused(), 0);
+        assert_eq!(
+            set.gc_metrics(),
+            GcMetrics {
+                total_inserts: 5,
+                total_collected: 4,
+                gc_runs: 3,
+                last_collected: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn panicking_insert_not_counted() {
+        let mut set = IndexedHashSet::<PanicHash>::new();
+        let _indices = (0..3)
+            .map(|i| set.insert(PanicHash(i)).unwrap())
+            .collect::<Vec<_>>();
+        while set.len() < set.map.capacity() {
+            let i = set.len() as u32;
+            let _ = set.insert(PanicHash(i));
+        }
+        let metrics = set.gc_metrics();
+
+        // hashing the new element itself panics
+        PANIC_ON.with(|p| p.set(Some(1000)));
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _ = set.insert(PanicHash(1000));
+        }));
+        assert!(result.is_err());
+        // growing the map rehashes `PanicHash(0)`
+        PANIC_ON.with(|p| p.set(Some(0)));
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _ = set.insert(PanicHash(1001));
+        }));
+        PANIC_ON.with(|p| p.set(None));
+        assert!(result.is_err());
+
+        assert_eq!(set.gc_metrics(), metrics);
+        let _ = set.insert(PanicHash(1001));
+        assert_eq!(set.gc_metrics().total_inserts, metrics.total_inserts + 1);
     }
 }
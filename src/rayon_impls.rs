@@ -0,0 +1,84 @@
+//! Parallel operations based on `rayon`, enabled by the `rayon` feature.
+
+use crate::internal_ref::Wrap as _;
+use crate::{Counter, IndexedHashSet};
+use rayon::prelude::*;
+use std::hash::{BuildHasher, Hash};
+
+impl<T, C, S> IndexedHashSet<T, C, S>
+where
+    T: Eq + Hash + Sync,
+    C: Counter,
+    C::Handle: Sync,
+    S: BuildHasher,
+{
+    /// Drop all entries whose `usage_cnt` is zero, checking the counts in
+    /// parallel.
+    ///
+    /// The result is the same as of [`drop_unused()`](#method.drop_unused).
+    /// Only the search for unused entries is parallelized, the entries are
+    /// dropped on the current thread. This pays off for very large sets.
+    ///
+    /// _Note:_ The counts must be thread-safe, i.e. this is only available
+    /// with the [`ArcCounter`]. Use `drop_unused()` with the default
+    /// `RcCounter`.
+    ///
+    /// [`ArcCounter`]: ./enum.ArcCounter.html
+    pub fn par_drop_unused(&mut self) -> usize {
+        if !C::take(&self.dirty) {
+            return self.record_gc(0);
+        }
+
+        let arena = &self.arena;
+        let unused = (0..self.arena.capacity())
+            .into_par_iter()
+            .filter_map(|slot| {
+                // only the usage counts and pinned flags are read
+                let (entry, a_idx) = arena.get_unknown_gen(slot)?;
+                if entry.is_used() {
+                    None
+                } else {
+                    Some(a_idx)
+                }
+            })
+            .collect::<Vec<_>>();
+
+        for a_idx in &unused {
+            let entry = self.arena.remove(*a_idx).unwrap();
+            self.map.remove(entry.elem().wrap());
+        }
+        self.record_gc(unused.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ArcCounter;
+
+    #[test]
+    fn same_as_sequential() {
+        let build = || {
+            let mut set = IndexedHashSet::<u32, ArcCounter>::default();
+            let indices = (0..10_000u32)
+                .filter_map(|i| {
+                    let idx = set.insert(i).unwrap();
+                    if i % 3 == 0 {
+                        Some(idx)
+                    } else {
+                        None
+                    }
+                })
+                .collect::<Vec<_>>();
+            set.pin(&1);
+            (set, indices)
+        };
+
+        let (mut seq, _seq_indices) = build();
+        let (mut par, _par_indices) = build();
+        assert_eq!(par.par_drop_unused(), seq.drop_unused());
+        assert_eq!(par.len(), seq.len());
+        assert!(seq.iter().all(|elem| par.contains(elem)));
+        assert_eq!(par.par_drop_unused(), 0);
+    }
+}
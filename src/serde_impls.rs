@@ -0,0 +1,108 @@
+//! Implementations of `serde`'s traits, enabled by the `serde` feature.
+//!
+//! A set is (de)serialized as a sequence of all of its elements, including
+//! the unused ones. Usage counts are not preserved, i.e. all entries of a
+//! deserialized set are unused.
+
+use crate::{Counter, IndexedHashSet};
+use serde::de::{Deserialize, Deserializer, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeSeq, Serializer};
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+
+/// Upper bound of the memory reserved up front based on a size hint.
+///
+/// This prevents malicious inputs from allocating huge amounts of memory.
+const MAX_PREALLOC_BYTES: usize = 1024 * 1024;
+
+impl<T, C, H> Serialize for IndexedHashSet<T, C, H>
+where
+    T: Eq + Hash + Serialize,
+    C: Counter,
+    H: BuildHasher,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.len()))?;
+        for (elem, _) in self.iter_all() {
+            seq.serialize_element(elem)?;
+        }
+        seq.end()
+    }
+}
+
+impl<'de, T, C, H> Deserialize<'de> for IndexedHashSet<T, C, H>
+where
+    T: Eq + Hash + Deserialize<'de>,
+    C: Counter,
+    H: BuildHasher + Default,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(SetVisitor(PhantomData))
+    }
+}
+
+/// Visitor producing a set of type `S`.
+struct SetVisitor<S>(PhantomData<fn() -> S>);
+
+impl<'de, T, C, H> Visitor<'de> for SetVisitor<IndexedHashSet<T, C, H>>
+where
+    T: Eq + Hash + Deserialize<'de>,
+    C: Counter,
+    H: BuildHasher + Default,
+{
+    type Value = IndexedHashSet<T, C, H>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a sequence")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let max_prealloc = MAX_PREALLOC_BYTES / std::mem::size_of::<T>().max(1);
+        let mut set = IndexedHashSet::default();
+        // reserve once, so the following insertions don't grow the set
+        set.reserve(seq.size_hint().unwrap_or(0).min(max_prealloc));
+        while let Some(elem) = seq.next_element()? {
+            let _ = set.insert(elem);
+        }
+        Ok(set)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::de::value::{Error, SeqDeserializer};
+
+    #[test]
+    fn json_round_trip() {
+        let mut set = IndexedHashSet::new();
+        let _o = set.insert("Olaf".to_owned()).unwrap();
+        set.insert("Harald".to_owned()).unwrap();
+
+        let json = serde_json::to_string(&set).unwrap();
+        let de: IndexedHashSet<String> = serde_json::from_str(&json).unwrap();
+        assert_eq!(de.len(), 2);
+        assert!(de.contains_all(vec!["Olaf", "Harald"]));
+        assert_eq!(de.get_cnt("Olaf"), Some(0));
+
+        let large = (0..10_000).collect::<Vec<u32>>();
+        let json = serde_json::to_string(&large).unwrap();
+        let de: IndexedHashSet<u32> = serde_json::from_str(&json).unwrap();
+        assert_eq!(de.len(), large.len());
+    }
+
+    #[test]
+    fn reserve_from_size_hint() {
+        // JSON provides no size hint, so a deserializer with an exact hint is
+        // used
+        let n = 10_000;
+        let mut expected = IndexedHashSet::<u32>::new();
+        expected.reserve(n);
+
+        let de = SeqDeserializer::<_, Error>::new(0..n as u32);
+        let set = IndexedHashSet::<u32>::deserialize(de).unwrap();
+        assert_eq!(set.len(), n);
+        assert_eq!(set.capacity(), expected.capacity());
+    }
+}
@@ -0,0 +1,7 @@
+//! Checks that misuses of the sets are rejected by the compiler.
+
+#[test]
+fn compile_fail() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
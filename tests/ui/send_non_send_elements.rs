@@ -0,0 +1,8 @@
+use indexed_hash_set::{ArcCounter, IndexedHashSet};
+use std::rc::Rc;
+
+fn assert_send<S: Send>(_: S) {}
+
+fn main() {
+    assert_send(IndexedHashSet::<Rc<u32>, ArcCounter>::default());
+}
@@ -0,0 +1,7 @@
+use indexed_hash_set::IndexedHashSet;
+
+fn assert_send<S: Send>(_: S) {}
+
+fn main() {
+    assert_send(IndexedHashSet::<u32>::new());
+}